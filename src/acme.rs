@@ -0,0 +1,239 @@
+//! Adds and removes the TXT records for ACME DNS-01 challenges, so that this
+//! can be used as a hook by certbot or lego to get certificates.
+
+use std::env;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::opts::ChallengeArgs;
+use crate::provider::{DnsProvider, Providers, Rrset};
+use crate::{records, ClientError};
+
+/// The label the challenge records are put under, see RFC 8555 section 8.4.
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+/// Gets the domain and validation value from the arguments, or from the
+/// environment variables certbot sets for its hooks.
+pub(crate) fn challenge(args: &ChallengeArgs) -> Result<(String, String), ClientError> {
+    let from_env = |arg: &Option<String>, what: &str, var: &str| {
+        arg.clone()
+            .or_else(|| env::var(var).ok())
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| ClientError::ChallengeMissing(what.to_string(), var.to_string()))
+    };
+    Ok((
+        from_env(&args.domain, "domain", "CERTBOT_DOMAIN")?,
+        from_env(&args.validation, "validation value", "CERTBOT_VALIDATION")?,
+    ))
+}
+
+/// The name of the challenge record for a domain. Certbot gives the domain
+/// being validated, while lego gives the full name of the record.
+fn challenge_name(domain: &str) -> String {
+    let domain = domain.trim_end_matches('.');
+    // The challenge for a wildcard goes on the domain it covers
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    if domain.starts_with(&format!("{CHALLENGE_LABEL}.")) {
+        domain.to_string()
+    } else {
+        format!("{CHALLENGE_LABEL}.{domain}")
+    }
+}
+
+pub(crate) struct ChallengeHook<'h> {
+    conf: &'h Config,
+    providers: Providers,
+}
+
+/// Where the challenge record for a domain goes.
+struct ChallengeRecord<'h> {
+    /// The full name, for messages.
+    name: String,
+    zone: &'h str,
+    rrset_name: String,
+    provider: Arc<dyn DnsProvider>,
+}
+
+impl<'h> ChallengeHook<'h> {
+    pub(crate) fn new(conf: &'h Config) -> Result<Self, ClientError> {
+        Ok(ChallengeHook {
+            conf,
+            providers: Providers::new(conf)?,
+        })
+    }
+
+    fn record(&self, domain: &str) -> Result<ChallengeRecord<'h>, ClientError> {
+        let name = challenge_name(domain);
+        let (zone, rrset_name) = Config::find_zone(self.conf, &name)
+            .ok_or_else(|| ClientError::ChallengeNoZone(name.clone()))?;
+        Ok(ChallengeRecord {
+            provider: self.providers.get(Config::zone_provider(self.conf, zone)),
+            name,
+            zone,
+            rrset_name,
+        })
+    }
+
+    /// Adds the validation value to the challenge record of the domain.
+    pub(crate) async fn set_txt(&self, domain: &str, validation: &str) -> Result<(), ClientError> {
+        let record = self.record(domain)?;
+        let value = records::api_value("TXT", validation);
+        // Keep any values that are already there, a certificate for both a
+        // domain and its wildcard has two challenges on the same record
+        let mut rrset = record
+            .provider
+            .get_rrset(record.zone, &record.rrset_name, "TXT")
+            .await?
+            .unwrap_or(Rrset {
+                values: Vec::new(),
+                ttl: self.conf.ttl,
+            });
+        if rrset.values.contains(&value) {
+            println!("The TXT record {} already has the challenge", record.name);
+            return Ok(());
+        }
+        rrset.values.push(value);
+        record
+            .provider
+            .set_rrset(record.zone, &record.rrset_name, "TXT", &rrset)
+            .await?;
+        println!("Added the challenge to the TXT record {}", record.name);
+        Ok(())
+    }
+
+    /// Removes the validation value from the challenge record of the domain,
+    /// and removes the record once no values are left.
+    pub(crate) async fn clear_txt(
+        &self,
+        domain: &str,
+        validation: &str,
+    ) -> Result<(), ClientError> {
+        let record = self.record(domain)?;
+        let value = records::api_value("TXT", validation);
+        let mut rrset = match record
+            .provider
+            .get_rrset(record.zone, &record.rrset_name, "TXT")
+            .await?
+        {
+            Some(rrset) if rrset.values.contains(&value) => rrset,
+            _ => {
+                println!("The TXT record {} doesn't have the challenge", record.name);
+                return Ok(());
+            }
+        };
+        rrset.values.retain(|current| current != &value);
+        if rrset.values.is_empty() {
+            record
+                .provider
+                .delete_rrset(record.zone, &record.rrset_name, "TXT")
+                .await?;
+        } else {
+            record
+                .provider
+                .set_rrset(record.zone, &record.rrset_name, "TXT", &rrset)
+                .await?;
+        }
+        println!("Removed the challenge from the TXT record {}", record.name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+
+    use super::{challenge_name, ChallengeHook};
+    use crate::{config::Config, ClientError};
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "xxx"
+ttl = 300
+
+[[entry]]
+name = "@"
+
+[[entry]]
+name = "@"
+fqdn = "example.org"
+"#,
+        )
+        .expect("Failed to parse config")
+    }
+
+    #[test]
+    fn challenge_name_test() {
+        assert_eq!(challenge_name("example.com"), "_acme-challenge.example.com");
+        assert_eq!(
+            challenge_name("*.example.com"),
+            "_acme-challenge.example.com"
+        );
+        assert_eq!(
+            challenge_name("_acme-challenge.www.example.com."),
+            "_acme-challenge.www.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_txt_test() {
+        let server = MockServer::start();
+        let mut conf = config();
+        conf.gandi.api_url = server.base_url();
+        let get = server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.org/records/_acme-challenge.www/TXT");
+            then.status(200)
+                .body(r#"{"rrset_values": ["\"first\""], "rrset_ttl": 300}"#);
+        });
+        let put = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.org/records/_acme-challenge.www/TXT")
+                .body_contains(r#"["\"first\"","\"second\""]"#);
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let hook = ChallengeHook::new(&conf).expect("Failed to create hook");
+        hook.set_txt("www.example.org", "second")
+            .await
+            .expect("Failed to set the challenge");
+        get.assert();
+        put.assert();
+
+        // Already there, so nothing is changed
+        hook.set_txt("_acme-challenge.www.example.org.", "first")
+            .await
+            .expect("Failed to set the challenge");
+        put.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn clear_txt_test() {
+        let server = MockServer::start();
+        let mut conf = config();
+        conf.gandi.api_url = server.base_url();
+        let get = server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.com/records/_acme-challenge/TXT");
+            then.status(200)
+                .body(r#"{"rrset_values": ["\"token\""], "rrset_ttl": 300}"#);
+        });
+        let delete = server.mock(|when, then| {
+            when.method("DELETE")
+                .path("/v5/livedns/domains/example.com/records/_acme-challenge/TXT");
+            then.status(204);
+        });
+
+        let hook = ChallengeHook::new(&conf).expect("Failed to create hook");
+        hook.clear_txt("example.com", "token")
+            .await
+            .expect("Failed to clear the challenge");
+        get.assert();
+        delete.assert();
+
+        let result = hook.clear_txt("example.net", "token").await;
+        assert!(matches!(result, Err(ClientError::ChallengeNoZone(_))));
+    }
+}
@@ -1,8 +1,12 @@
-use crate::opts;
+use crate::{opts, records};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use directories::ProjectDirs;
-use serde::Deserialize;
-use std::path::PathBuf;
-use std::{fs, io};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, iter};
 use thiserror::Error;
 
 fn default_types() -> Vec<String> {
@@ -19,8 +23,55 @@ pub enum ConfigError {
     Validation(String, String),
     #[error("Can't find config directory")]
     ConfigNotFound(),
+    #[error("Invalid regex for the custom IP source: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error(
+        "ip_source_quorum is {0}, but it must be between 1 and the number of IP sources ({1})"
+    )]
+    InvalidQuorum(usize, usize),
+    #[error("IPv6 prefix length of entry '{0}' is {1}, but it must be at most 128")]
+    InvalidPrefixLength(String, u8),
+    #[error("IPv6 suffix {1} of entry '{0}' overlaps with the /{2} prefix")]
+    InvalidIpv6Suffix(String, Ipv6Addr, u8),
+    #[error("Entry '{0}' has type '{1}', which needs a list of values")]
+    MissingValues(String, String),
+    #[error("Entry '{0}' has values, but no type to say what kind of record they are for")]
+    MissingType(String),
+    #[error("Entry '{0}' has an invalid {1} value '{2}': {3}")]
+    InvalidValue(String, String, String, String),
+    #[error("Entry '{0}' is a CNAME, which must have exactly one value and can't be the root of the domain")]
+    InvalidCname(String),
+    #[error("Failed to read the API key from '{0}': {1}")]
+    ApiKeyFile(String, io::Error),
+    #[error("No API key found for provider '{0}'. Set it in the config, directly, with a file or with an environment variable, or provide the '{1}' systemd credential or Docker secret")]
+    MissingApiKey(String, String),
+    #[error("Entry '{0}' uses provider '{1}', which is not in the config")]
+    UnknownProvider(String, String),
+    #[error("Failed to read the TSIG secret of provider '{0}' from '{1}': {2}")]
+    SecretFile(String, String, io::Error),
+    #[error("The TSIG secret of provider '{0}' is not valid base64: {1}")]
+    InvalidSecret(String, String),
+    #[error("Failed to read the password of host '{0}' from '{1}': {2}")]
+    PasswordFile(String, String, io::Error),
+    #[error("Host '{0}' in the serve section has no password")]
+    MissingPassword(String),
+    #[error("Host '{0}' in the serve section is not in the domain of the config or of any entry")]
+    UnknownServeHost(String),
+    #[error("Serving dyndns2 updates needs at least one [[serve.host]] in the config")]
+    NoServeHosts(),
+    #[error("Provider '{0}' uses the dyndns2 protocol, which can't read records back. Set state or state_file so the updates aren't sent again on every start")]
+    Dyndns2WithoutState(String),
 }
 
+/// The name of the systemd credential or Docker secret holding the API key
+/// of the default provider. Other providers use their name instead of `gandi`.
+const API_KEY_CREDENTIAL: &str = "gandi_api_key";
+/// The provider of entries that don't name one, set up by the top level of
+/// the config unless there is a provider with this name.
+pub const DEFAULT_PROVIDER: &str = "default";
+/// Where Docker mounts secrets.
+const SECRETS_DIR: &str = "/run/secrets";
+
 #[derive(Deserialize, Debug)]
 pub struct Entry {
     pub name: String,
@@ -28,17 +79,154 @@ pub struct Entry {
     types: Vec<String>,
     fqdn: Option<String>,
     ttl: Option<u32>,
+    /// Publish this host part combined with the detected IPv6 prefix, rather
+    /// than the detected address itself. Useful for other machines on a
+    /// delegated prefix, e.g. `::1234`.
+    ipv6_suffix: Option<Ipv6Addr>,
+    ipv6_prefix_length: Option<u8>,
+    /// The type of a record with static `values`, like TXT or CNAME.
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+    values: Option<Vec<String>>,
+    /// The name of the provider to publish this entry with.
+    provider: Option<String>,
 }
 
 fn default_ttl() -> u32 {
     300
 }
 
+fn default_retry_budget() -> u64 {
+    60
+}
+
+fn default_ipv6_prefix_length() -> u8 {
+    64
+}
+
+fn default_dns_port() -> u16 {
+    53
+}
+
+fn default_gandi_url() -> String {
+    "https://api.gandi.net".to_string()
+}
+
+fn default_cloudflare_url() -> String {
+    "https://api.cloudflare.com/client/v4".to_string()
+}
+
+fn default_serve_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8245))
+}
+
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 pub enum IPSourceName {
     Ipify,
     Icanhazip,
     SeeIP,
+    /// Read the address from a local network interface, e.g. `eth0`.
+    #[serde(rename = "interface")]
+    Interface(String),
+    /// Query a user-provided HTTP service.
+    #[serde(rename = "custom")]
+    Custom(CustomIPSource),
+    /// Send a DNS query to a server that responds with the address it sees.
+    #[serde(rename = "dns")]
+    Dns(DnsIPSource),
+    /// Ask a STUN server which address our request came from.
+    #[serde(rename = "stun")]
+    Stun(StunIPSource),
+    /// Ask the router for its external address.
+    #[serde(rename = "gateway")]
+    Gateway(GatewayIPSource),
+    /// Run a command that prints the address.
+    #[serde(rename = "command")]
+    Command(String),
+    /// Read the address from a file.
+    #[serde(rename = "file")]
+    File(PathBuf),
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct CustomIPSource {
+    pub ipv4_url: Option<String>,
+    pub ipv6_url: Option<String>,
+    #[serde(default)]
+    pub extract: CustomExtract,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct DnsIPSource {
+    /// The name to query.
+    pub name: String,
+    /// The DNS server to send the query to, as a hostname or an IP address.
+    pub resolver: String,
+    pub port: u16,
+    /// Read the address from a TXT record, rather than an A or AAAA record.
+    pub txt: bool,
+}
+
+impl Default for DnsIPSource {
+    fn default() -> Self {
+        DnsIPSource {
+            name: "myip.opendns.com".to_string(),
+            resolver: "resolver1.opendns.com".to_string(),
+            port: 53,
+            txt: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct StunIPSource {
+    /// The STUN server, as a hostname or an IP address.
+    pub server: String,
+    pub port: u16,
+}
+
+impl Default for StunIPSource {
+    fn default() -> Self {
+        StunIPSource {
+            server: "stun.cloudflare.com".to_string(),
+            port: 3478,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct GatewayIPSource {
+    pub protocol: GatewayProtocol,
+    /// The address of the gateway. Defaults to the default gateway.
+    pub address: Option<IpAddr>,
+    /// The port NAT-PMP or PCP requests are sent to, if not the standard one.
+    pub port: Option<u16>,
+    /// The URL of the UPnP device description, to skip discovery.
+    pub location: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GatewayProtocol {
+    UPnP,
+    NatPmp,
+    Pcp,
+}
+
+/// How to get the IP address out of the response of a custom IP source.
+#[derive(Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum CustomExtract {
+    /// The response is the IP address, with maybe some whitespace around it.
+    #[default]
+    Text,
+    /// Use the first capture group of the regex, or the whole match if the
+    /// regex has no capture groups.
+    #[serde(rename = "regex")]
+    Regex(String),
+    /// The response is JSON, and the IP address is the string at this pointer.
+    #[serde(rename = "json_pointer")]
+    JsonPointer(String),
 }
 
 impl Default for IPSourceName {
@@ -49,17 +237,274 @@ impl Default for IPSourceName {
     }
 }
 
+fn default_ip_sources() -> Vec<IPSourceName> {
+    vec![IPSourceName::default()]
+}
+
+/// Accepts either a single IP source, or a list of them.
+fn one_or_many_ip_sources<'de, D>(deserializer: D) -> Result<Vec<IPSourceName>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(IPSourceName),
+        Many(Vec<IPSourceName>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(source) => vec![source],
+        OneOrMany::Many(sources) => sources,
+    })
+}
+
+/// How to authenticate with the Gandi API.
+#[derive(Deserialize, Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub enum AuthType {
+    /// The deprecated API key.
+    #[default]
+    ApiKey,
+    /// A personal access token.
+    Pat,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GandiConfig {
+    /// If this is not in the config file, `load_config` fills it in from one
+    /// of the other places the key can be kept.
+    #[serde(default)]
+    pub api_key: String,
+    /// Read the API key from this file.
+    pub api_key_file: Option<PathBuf>,
+    /// Read the API key from this environment variable.
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub auth_type: AuthType,
+    /// Where the API is, e.g. to use the Gandi sandbox.
+    #[serde(default = "default_gandi_url")]
+    pub api_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rfc2136Config {
+    /// The primary server of the zones, as a hostname or an IP address.
+    pub server: String,
+    #[serde(default = "default_dns_port")]
+    pub port: u16,
+    /// The name of the TSIG key, as it is configured on the server.
+    pub key_name: String,
+    /// The HMAC-SHA256 secret of the key, base64 encoded like in the key
+    /// files of BIND and Knot.
+    #[serde(default)]
+    pub key_secret: String,
+    /// Read the secret from this file.
+    pub key_secret_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CloudflareConfig {
+    /// An API token with permission to edit the DNS of the zones. Filled in
+    /// by `load_config` like the Gandi API key.
+    #[serde(default)]
+    pub api_token: String,
+    /// Read the API token from this file.
+    pub api_token_file: Option<PathBuf>,
+    /// Read the API token from this environment variable.
+    pub api_token_env: Option<String>,
+    /// Whether new records go through the Cloudflare proxy. Existing records
+    /// keep their setting unless this is given.
+    pub proxied: Option<bool>,
+    #[serde(default = "default_cloudflare_url")]
+    pub api_url: String,
+}
+
+/// A DNS provider, with the `type` field saying which one.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProviderConfig {
+    Gandi(GandiConfig),
+    /// Dynamic updates sent to an authoritative server, see RFC 2136.
+    Rfc2136(Rfc2136Config),
+    Cloudflare(CloudflareConfig),
+    /// Any service that takes updates with the dyndns2 protocol.
+    Dyndns2(Dyndns2Config),
+    PowerDns(PowerDnsConfig),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Dyndns2Config {
+    /// The update URL of the service, e.g.
+    /// `https://dynupdate.no-ip.com/nic/update`.
+    pub url: String,
+    pub username: String,
+    /// Filled in by `load_config` like the Gandi API key.
+    #[serde(default)]
+    pub password: String,
+    /// Read the password from this file.
+    pub password_file: Option<PathBuf>,
+    /// Read the password from this environment variable.
+    pub password_env: Option<String>,
+}
+
+fn default_powerdns_server() -> String {
+    "localhost".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PowerDnsConfig {
+    /// Where the API of the authoritative server is, e.g.
+    /// `http://127.0.0.1:8081`.
+    pub api_url: String,
+    /// Filled in by `load_config` like the Gandi API key.
+    #[serde(default)]
+    pub api_key: String,
+    /// Read the API key from this file.
+    pub api_key_file: Option<PathBuf>,
+    /// Read the API key from this environment variable.
+    pub api_key_env: Option<String>,
+    /// The server ID in the API paths, which is always `localhost` for the
+    /// authoritative server itself.
+    #[serde(default = "default_powerdns_server")]
+    pub server_id: String,
+    /// How many requests per minute to send at most, if the server is behind
+    /// something that limits them.
+    pub rate_limit: Option<NonZeroU32>,
+}
+
+/// Serving the dyndns2 protocol, so routers can push their address to us.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServeConfig {
+    /// The address and port to listen on for HTTP requests. There is no TLS,
+    /// so this should only be reachable by a reverse proxy that adds it.
+    #[serde(default = "default_serve_listen")]
+    pub listen: SocketAddr,
+    /// Reverse proxies in front of the server. Only requests from these have
+    /// their `X-Forwarded-For` header believed.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// The hosts clients may update, each with its own credentials.
+    #[serde(default, rename = "host")]
+    pub hosts: Vec<ServeHost>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServeHost {
+    /// The full name of the record, in one of the configured domains.
+    pub hostname: String,
+    pub username: String,
+    /// If this is not in the config file, `load_config` reads it from the
+    /// `password_file`.
+    #[serde(default)]
+    pub password: String,
+    pub password_file: Option<PathBuf>,
+    pub ttl: Option<u32>,
+}
+
+/// Where the API key of a provider can be given in the config.
+trait ApiKeySources {
+    fn key(&self) -> &str;
+    fn key_file(&self) -> Option<&Path>;
+    fn key_env(&self) -> Option<&str>;
+}
+
+impl ApiKeySources for GandiConfig {
+    fn key(&self) -> &str {
+        &self.api_key
+    }
+    fn key_file(&self) -> Option<&Path> {
+        self.api_key_file.as_deref()
+    }
+    fn key_env(&self) -> Option<&str> {
+        self.api_key_env.as_deref()
+    }
+}
+
+impl ApiKeySources for CloudflareConfig {
+    fn key(&self) -> &str {
+        &self.api_token
+    }
+    fn key_file(&self) -> Option<&Path> {
+        self.api_token_file.as_deref()
+    }
+    fn key_env(&self) -> Option<&str> {
+        self.api_token_env.as_deref()
+    }
+}
+
+impl ApiKeySources for PowerDnsConfig {
+    fn key(&self) -> &str {
+        &self.api_key
+    }
+    fn key_file(&self) -> Option<&Path> {
+        self.api_key_file.as_deref()
+    }
+    fn key_env(&self) -> Option<&str> {
+        self.api_key_env.as_deref()
+    }
+}
+
+impl ApiKeySources for Dyndns2Config {
+    fn key(&self) -> &str {
+        &self.password
+    }
+    fn key_file(&self) -> Option<&Path> {
+        self.password_file.as_deref()
+    }
+    fn key_env(&self) -> Option<&str> {
+        self.password_env.as_deref()
+    }
+}
+
+/// How to combine the answers when multiple IP sources are configured.
+#[derive(Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum IPSourceMode {
+    /// Try the sources in order, and use the first one that succeeds.
+    #[default]
+    Fallback,
+    /// Query all sources, and only use an address if enough of them agree.
+    Quorum,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     fqdn: String,
-    pub api_key: String,
+    /// The Gandi account of the default provider.
+    #[serde(flatten)]
+    pub gandi: GandiConfig,
+    /// More providers that entries can use, by name.
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderConfig>,
+    #[serde(
+        default = "default_ip_sources",
+        deserialize_with = "one_or_many_ip_sources"
+    )]
+    pub ip_source: Vec<IPSourceName>,
+    #[serde(default)]
+    pub ip_source_mode: IPSourceMode,
+    /// How many sources have to agree in quorum mode. Defaults to a majority.
+    pub ip_source_quorum: Option<usize>,
     #[serde(default)]
-    pub ip_source: IPSourceName,
     pub entry: Vec<Entry>,
     #[serde(default = "default_ttl")]
     pub ttl: u32,
     #[serde(default)]
     pub always_update: bool,
+    /// How many seconds to keep retrying a request when the API is overloaded
+    /// or can't be reached.
+    #[serde(default = "default_retry_budget")]
+    pub retry_budget: u64,
+    /// How much of the detected IPv6 address is the prefix, for entries with
+    /// an `ipv6_suffix`.
+    #[serde(default = "default_ipv6_prefix_length")]
+    pub ipv6_prefix_length: u8,
+    /// Remember what was published between runs, in the default location.
+    #[serde(default)]
+    pub state: bool,
+    /// Remember what was published between runs, in this file.
+    pub state_file: Option<PathBuf>,
+    /// Accept updates from dyndns2 clients with the `serve` command.
+    pub serve: Option<ServeConfig>,
 }
 
 const DEFAULT_TYPES: &[&str] = &["A"];
@@ -74,10 +519,163 @@ impl Config {
     }
 
     pub fn types(entry: &Entry) -> Vec<&str> {
-        entry.types.iter().map(|t| t.as_str()).collect()
+        match &entry.record_type {
+            Some(record_type) => vec![record_type.as_str()],
+            None => entry.types.iter().map(|t| t.as_str()).collect(),
+        }
+    }
+
+    /// The values of a static record, formatted for the API. `None` if the
+    /// record is an address that has to be found out.
+    pub fn values(entry: &Entry) -> Option<Vec<String>> {
+        let record_type = entry.record_type.as_ref()?;
+        Some(
+            entry
+                .values
+                .as_ref()?
+                .iter()
+                .map(|value| records::api_value(record_type, value))
+                .collect(),
+        )
+    }
+
+    pub fn ipv6_prefix_length(entry: &Entry, config: &Config) -> u8 {
+        entry
+            .ipv6_prefix_length
+            .unwrap_or(config.ipv6_prefix_length)
+    }
+
+    /// The IPv6 address to publish for the entry, given the detected one.
+    pub fn ipv6(entry: &Entry, config: &Config, detected: Ipv6Addr) -> Ipv6Addr {
+        match entry.ipv6_suffix {
+            Some(suffix) => {
+                let mask = prefix_mask(Config::ipv6_prefix_length(entry, config));
+                Ipv6Addr::from((u128::from(detected) & mask) | (u128::from(suffix) & !mask))
+            }
+            None => detected,
+        }
+    }
+
+    /// Where to keep the state between runs, if anywhere. Uses the directory
+    /// systemd gives us if there is one.
+    pub fn state_file(config: &Config) -> Option<PathBuf> {
+        if let Some(path) = &config.state_file {
+            return Some(path.clone());
+        }
+        if !config.state {
+            return None;
+        }
+        let dir = match env::var_os("STATE_DIRECTORY") {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                let dirs = ProjectDirs::from("me", "kaangenc", "gandi-dynamic-dns")?;
+                dirs.state_dir()
+                    .unwrap_or(dirs.data_local_dir())
+                    .to_path_buf()
+            }
+        };
+        Some(dir.join("state.json"))
+    }
+
+    /// Splits a host name into the domain it belongs to and the record name
+    /// within that domain. Only the domains in the config are considered, and
+    /// the longest one that matches wins.
+    pub fn find_zone<'c>(config: &'c Config, hostname: &str) -> Option<(&'c str, String)> {
+        let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+        iter::once(config.fqdn.as_str())
+            .chain(
+                config
+                    .entry
+                    .iter()
+                    .filter_map(|entry| entry.fqdn.as_deref()),
+            )
+            .filter_map(|zone| {
+                let suffix = zone.trim_end_matches('.').to_ascii_lowercase();
+                if hostname == suffix {
+                    return Some((zone, "@".to_string()));
+                }
+                let name = hostname.strip_suffix(&suffix)?.strip_suffix('.')?;
+                Some((zone, name.to_string()))
+            })
+            .max_by_key(|(zone, _)| zone.len())
+    }
+
+    /// The name of the provider the entry is published with.
+    pub fn provider(entry: &Entry) -> &str {
+        entry.provider.as_deref().unwrap_or(DEFAULT_PROVIDER)
+    }
+
+    /// The provider of the entries in this domain, or the default one if
+    /// there are none.
+    pub fn zone_provider<'c>(config: &'c Config, zone: &str) -> &'c str {
+        config
+            .entry
+            .iter()
+            .find(|entry| Config::fqdn(entry, config) == zone)
+            .map_or(DEFAULT_PROVIDER, Config::provider)
+    }
+
+    pub fn ip_source_quorum(config: &Config) -> usize {
+        config
+            .ip_source_quorum
+            .unwrap_or(config.ip_source.len() / 2 + 1)
     }
 }
 
+/// The bits of an IPv6 address that belong to a prefix of this length.
+fn prefix_mask(prefix_length: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_length.min(128)))
+        .unwrap_or(0)
+}
+
+/// Reads a key from a file, ignoring the whitespace around it.
+fn read_api_key(path: &Path) -> Result<String, io::Error> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Finds the API key, trying the config file, the file or environment
+/// variable it points to, then the systemd credentials and Docker secrets.
+fn find_api_key(
+    provider: &str,
+    config: &impl ApiKeySources,
+    credential: &str,
+    credentials_dir: Option<&Path>,
+    secrets_dir: &Path,
+) -> Result<String, ConfigError> {
+    if !config.key().is_empty() {
+        return Ok(config.key().to_string());
+    }
+    if let Some(path) = config.key_file() {
+        return read_api_key(path)
+            .map_err(|err| ConfigError::ApiKeyFile(path.to_string_lossy().to_string(), err));
+    }
+    if let Some(key) = config.key_env().and_then(|var| env::var(var).ok()) {
+        let key = key.trim();
+        if !key.is_empty() {
+            return Ok(key.to_string());
+        }
+    }
+    for dir in credentials_dir.into_iter().chain([secrets_dir]) {
+        let path = dir.join(credential);
+        match read_api_key(&path) {
+            Ok(key) if !key.is_empty() => return Ok(key),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(ConfigError::ApiKeyFile(
+                    path.to_string_lossy().to_string(),
+                    err,
+                ))
+            }
+        }
+    }
+    Err(ConfigError::MissingApiKey(
+        provider.to_string(),
+        credential.to_string(),
+    ))
+}
+
 fn load_config_from<P: std::convert::AsRef<std::path::Path>>(
     path: P,
 ) -> Result<Config, ConfigError> {
@@ -89,30 +687,132 @@ pub fn load_config(opts: &opts::Opts) -> Result<Config, ConfigError> {
     let mut config = match &opts.config {
         Some(config_path) => load_config_from(config_path),
         None => {
+            // Only the report goes to stdout in JSON mode
+            let text = opts.output == opts::OutputFormat::Text;
             let confpath = ProjectDirs::from("me", "kaangenc", "gandi-dynamic-dns")
                 .map(|dir| PathBuf::from(dir.config_dir()).join("config.toml"))
                 .ok_or(ConfigError::ConfigNotFound());
             confpath
                 .and_then(|path| {
-                    println!("Checking for config: {}", path.to_string_lossy());
+                    if text {
+                        println!("Checking for config: {}", path.to_string_lossy());
+                    }
                     load_config_from(path)
                 })
                 .or_else(|_| {
                     let path = PathBuf::from(".").join("gandi.toml");
-                    println!("Checking for config: {}", path.to_string_lossy());
+                    if text {
+                        println!("Checking for config: {}", path.to_string_lossy());
+                    }
                     load_config_from(path)
                 })
         }
     }?;
+    let credentials_dir = env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+    let secrets_dir = Path::new(SECRETS_DIR);
+    // Only look for the top level key if something uses it, a config may
+    // have nothing but named providers
+    let serve_uses_default = config
+        .serve
+        .iter()
+        .flat_map(|serve| &serve.hosts)
+        .filter_map(|host| Config::find_zone(&config, &host.hostname))
+        .any(|(zone, _)| Config::zone_provider(&config, zone) == DEFAULT_PROVIDER);
+    if !config.providers.contains_key(DEFAULT_PROVIDER)
+        && (serve_uses_default
+            || config
+                .entry
+                .iter()
+                .any(|entry| Config::provider(entry) == DEFAULT_PROVIDER))
+    {
+        config.gandi.api_key = find_api_key(
+            DEFAULT_PROVIDER,
+            &config.gandi,
+            API_KEY_CREDENTIAL,
+            credentials_dir.as_deref(),
+            secrets_dir,
+        )?;
+    }
+    for (name, provider) in config.providers.iter_mut() {
+        match provider {
+            ProviderConfig::Gandi(gandi) => {
+                gandi.api_key = find_api_key(
+                    name,
+                    gandi,
+                    &format!("{name}_api_key"),
+                    credentials_dir.as_deref(),
+                    secrets_dir,
+                )?;
+            }
+            ProviderConfig::Rfc2136(rfc2136) => {
+                if let Some(path) = rfc2136
+                    .key_secret_file
+                    .as_ref()
+                    .filter(|_| rfc2136.key_secret.is_empty())
+                {
+                    rfc2136.key_secret = read_api_key(path).map_err(|err| {
+                        ConfigError::SecretFile(
+                            name.clone(),
+                            path.to_string_lossy().to_string(),
+                            err,
+                        )
+                    })?;
+                }
+            }
+            ProviderConfig::Cloudflare(cloudflare) => {
+                cloudflare.api_token = find_api_key(
+                    name,
+                    cloudflare,
+                    &format!("{name}_api_key"),
+                    credentials_dir.as_deref(),
+                    secrets_dir,
+                )?;
+            }
+            ProviderConfig::PowerDns(powerdns) => {
+                powerdns.api_key = find_api_key(
+                    name,
+                    powerdns,
+                    &format!("{name}_api_key"),
+                    credentials_dir.as_deref(),
+                    secrets_dir,
+                )?;
+            }
+            ProviderConfig::Dyndns2(dyndns2) => {
+                dyndns2.password = find_api_key(
+                    name,
+                    dyndns2,
+                    &format!("{name}_password"),
+                    credentials_dir.as_deref(),
+                    secrets_dir,
+                )?;
+            }
+        }
+    }
+    for host in config.serve.iter_mut().flat_map(|serve| &mut serve.hosts) {
+        if let Some(path) = host
+            .password_file
+            .as_ref()
+            .filter(|_| host.password.is_empty())
+        {
+            host.password = read_api_key(path).map_err(|err| {
+                ConfigError::PasswordFile(
+                    host.hostname.clone(),
+                    path.to_string_lossy().to_string(),
+                    err,
+                )
+            })?;
+        }
+    }
     // Filter out any types skipped in CLI opts
     if opts.skip_ipv4 || opts.skip_ipv6 {
         config.entry = config
             .entry
             .into_iter()
             .map(|mut entry| {
+                // Only drop the skipped address types, keep everything else
                 entry
                     .types
-                    .retain(|v| (v == "A" && !opts.skip_ipv4) || (v == "AAAA" && !opts.skip_ipv6));
+                    .retain(|v| (v != "A" || !opts.skip_ipv4) && (v != "AAAA" || !opts.skip_ipv6));
                 entry
             })
             .collect();
@@ -122,23 +822,127 @@ pub fn load_config(opts: &opts::Opts) -> Result<Config, ConfigError> {
 
 pub fn validate_config(config: &Config) -> Result<(), ConfigError> {
     for entry in &config.entry {
-        for entry_type in Config::types(entry) {
-            if entry_type != "A" && entry_type != "AAAA" {
-                return Err(ConfigError::Validation(
+        let provider = Config::provider(entry);
+        if provider != DEFAULT_PROVIDER && !config.providers.contains_key(provider) {
+            return Err(ConfigError::UnknownProvider(
+                entry.name.clone(),
+                provider.to_string(),
+            ));
+        }
+        match (&entry.record_type, &entry.values) {
+            (Some(record_type), Some(values)) => {
+                if !records::STATIC_TYPES.contains(&record_type.as_str()) {
+                    return Err(ConfigError::Validation(
+                        entry.name.clone(),
+                        record_type.clone(),
+                    ));
+                }
+                if values.is_empty() {
+                    return Err(ConfigError::MissingValues(
+                        entry.name.clone(),
+                        record_type.clone(),
+                    ));
+                }
+                if record_type == "CNAME" && (values.len() > 1 || entry.name == "@") {
+                    return Err(ConfigError::InvalidCname(entry.name.clone()));
+                }
+                for value in values {
+                    records::validate_value(record_type, value).map_err(|reason| {
+                        ConfigError::InvalidValue(
+                            entry.name.clone(),
+                            record_type.clone(),
+                            value.clone(),
+                            reason,
+                        )
+                    })?;
+                }
+            }
+            (Some(record_type), None) => {
+                return Err(ConfigError::MissingValues(
+                    entry.name.clone(),
+                    record_type.clone(),
+                ))
+            }
+            (None, Some(_)) => return Err(ConfigError::MissingType(entry.name.clone())),
+            (None, None) => {
+                for entry_type in Config::types(entry) {
+                    if entry_type != "A" && entry_type != "AAAA" {
+                        return Err(ConfigError::Validation(
+                            entry.name.clone(),
+                            entry_type.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        let prefix_length = Config::ipv6_prefix_length(entry, config);
+        if prefix_length > 128 {
+            return Err(ConfigError::InvalidPrefixLength(
+                entry.name.clone(),
+                prefix_length,
+            ));
+        }
+        if let Some(suffix) = entry.ipv6_suffix {
+            if u128::from(suffix) & prefix_mask(prefix_length) != 0 {
+                return Err(ConfigError::InvalidIpv6Suffix(
                     entry.name.clone(),
-                    entry_type.to_string(),
+                    suffix,
+                    prefix_length,
                 ));
             }
         }
     }
+    for (name, provider) in &config.providers {
+        match provider {
+            ProviderConfig::Rfc2136(rfc2136) => match STANDARD.decode(&rfc2136.key_secret) {
+                Ok(secret) if !secret.is_empty() => {}
+                Ok(_) => {
+                    return Err(ConfigError::InvalidSecret(
+                        name.clone(),
+                        "the secret is empty".to_string(),
+                    ))
+                }
+                Err(err) => return Err(ConfigError::InvalidSecret(name.clone(), err.to_string())),
+            },
+            // Services block clients that keep sending the same address
+            ProviderConfig::Dyndns2(_) if !config.state && config.state_file.is_none() => {
+                return Err(ConfigError::Dyndns2WithoutState(name.clone()));
+            }
+            _ => {}
+        }
+    }
+    for host in config.serve.iter().flat_map(|serve| &serve.hosts) {
+        if Config::find_zone(config, &host.hostname).is_none() {
+            return Err(ConfigError::UnknownServeHost(host.hostname.clone()));
+        }
+        if host.password.is_empty() {
+            return Err(ConfigError::MissingPassword(host.hostname.clone()));
+        }
+    }
+    if config.ip_source_mode == IPSourceMode::Quorum {
+        let quorum = Config::ip_source_quorum(config);
+        if quorum == 0 || quorum > config.ip_source.len() {
+            return Err(ConfigError::InvalidQuorum(quorum, config.ip_source.len()));
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::load_config;
-    use crate::{config::IPSourceName, opts::Opts};
-    use std::{env::temp_dir, fs};
+    use super::{
+        find_api_key, load_config, validate_config, Config, ConfigError, ProviderConfig,
+        API_KEY_CREDENTIAL, DEFAULT_PROVIDER,
+    };
+    use crate::{
+        config::{CustomExtract, CustomIPSource, IPSourceMode, IPSourceName},
+        opts::Opts,
+    };
+    use std::{
+        env::{self, temp_dir},
+        fs,
+        net::{IpAddr, Ipv6Addr},
+    };
 
     #[test]
     fn load_config_test() {
@@ -168,7 +972,7 @@ name = "@"
         let conf = load_config(&opts).expect("Failed to load config file");
 
         assert_eq!(conf.fqdn, "example.com");
-        assert_eq!(conf.api_key, "xxx");
+        assert_eq!(conf.gandi.api_key, "xxx");
         assert_eq!(conf.ttl, 300);
         assert_eq!(conf.entry.len(), 2);
         assert_eq!(conf.entry[0].name, "www");
@@ -176,7 +980,8 @@ name = "@"
         assert_eq!(conf.entry[1].name, "@");
         assert_eq!(conf.entry[1].types, vec!["A".to_string()]);
         // default
-        assert_eq!(conf.ip_source, IPSourceName::Ipify);
+        assert_eq!(conf.ip_source, vec![IPSourceName::Ipify]);
+        assert_eq!(conf.ip_source_mode, IPSourceMode::Fallback);
         assert_eq!(conf.always_update, false);
     }
 
@@ -210,15 +1015,153 @@ name = "@"
         let conf = load_config(&opts).expect("Failed to load config file");
 
         assert_eq!(conf.fqdn, "example.com");
-        assert_eq!(conf.api_key, "yyy");
+        assert_eq!(conf.gandi.api_key, "yyy");
         assert_eq!(conf.ttl, 1200);
         assert_eq!(conf.entry.len(), 2);
         assert_eq!(conf.entry[0].name, "www");
         assert_eq!(conf.entry[1].name, "@");
-        assert_eq!(conf.ip_source, IPSourceName::Icanhazip);
+        assert_eq!(conf.ip_source, vec![IPSourceName::Icanhazip]);
         assert_eq!(conf.always_update, true);
     }
 
+    #[test]
+    fn load_config_interface_ip_source() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-5.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+ip_source = { interface = "eth0" }
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert_eq!(
+            conf.ip_source,
+            vec![IPSourceName::Interface("eth0".to_string())]
+        );
+    }
+
+    #[test]
+    fn load_config_custom_ip_source() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-6.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+
+[ip_source.custom]
+ipv4_url = "https://echo.example.com/ip"
+extract = { json_pointer = "/ip" }
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert_eq!(
+            conf.ip_source,
+            vec![IPSourceName::Custom(CustomIPSource {
+                ipv4_url: Some("https://echo.example.com/ip".to_string()),
+                ipv6_url: None,
+                extract: CustomExtract::JsonPointer("/ip".to_string()),
+            })]
+        );
+    }
+
+    #[test]
+    fn load_config_multiple_ip_sources() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-7.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+ip_source = ["Ipify", "SeeIP", { interface = "eth0" }]
+ip_source_mode = "Quorum"
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert_eq!(
+            conf.ip_source,
+            vec![
+                IPSourceName::Ipify,
+                IPSourceName::SeeIP,
+                IPSourceName::Interface("eth0".to_string())
+            ]
+        );
+        assert_eq!(conf.ip_source_mode, IPSourceMode::Quorum);
+        // Defaults to a majority
+        assert_eq!(Config::ip_source_quorum(&conf), 2);
+        validate_config(&conf).expect("Config should be valid");
+    }
+
+    #[test]
+    fn validate_config_quorum_too_large() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-8.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+ip_source = ["Ipify", "SeeIP"]
+ip_source_mode = "Quorum"
+ip_source_quorum = 3
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert!(matches!(
+            validate_config(&conf),
+            Err(ConfigError::InvalidQuorum(3, 2))
+        ));
+    }
+
     #[test]
     fn load_config_skip_ipv4_with_opts() {
         let mut temp = temp_dir().join("gandi-live-dns-test");
@@ -249,7 +1192,7 @@ types = ["A", "AAAA"]
         let conf = load_config(&opts).expect("Failed to load config file");
 
         assert_eq!(conf.fqdn, "example.com");
-        assert_eq!(conf.api_key, "yyy");
+        assert_eq!(conf.gandi.api_key, "yyy");
         assert_eq!(conf.entry.len(), 2);
         assert_eq!(conf.entry[0].name, "www");
         assert_eq!(conf.entry[0].types, vec!["AAAA".to_string()]);
@@ -287,11 +1230,531 @@ types = ["A", "AAAA"]
         let conf = load_config(&opts).expect("Failed to load config file");
 
         assert_eq!(conf.fqdn, "example.com");
-        assert_eq!(conf.api_key, "yyy");
+        assert_eq!(conf.gandi.api_key, "yyy");
         assert_eq!(conf.entry.len(), 2);
         assert_eq!(conf.entry[0].name, "www");
         assert_eq!(conf.entry[0].types, vec!["A".to_string()]);
         assert_eq!(conf.entry[1].name, "@");
         assert_eq!(conf.entry[1].types, vec!["A".to_string()]);
     }
+
+    #[test]
+    fn load_config_ipv6_suffix() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-9.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+
+[[entry]]
+name = "www"
+types = ["AAAA"]
+
+[[entry]]
+name = "nas"
+types = ["AAAA"]
+ipv6_suffix = "::1234"
+
+[[entry]]
+name = "printer"
+types = ["AAAA"]
+ipv6_suffix = "::5:0:0:0:1"
+ipv6_prefix_length = 56
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+        validate_config(&conf).expect("Config should be valid");
+
+        let detected: Ipv6Addr = "2001:db8:aa:bbcc:1:2:3:4".parse().unwrap();
+        assert_eq!(Config::ipv6(&conf.entry[0], &conf, detected), detected);
+        assert_eq!(
+            Config::ipv6(&conf.entry[1], &conf, detected),
+            "2001:db8:aa:bbcc::1234".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            Config::ipv6(&conf.entry[2], &conf, detected),
+            "2001:db8:aa:bb05::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn validate_config_ipv6_suffix_overlaps_prefix() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-10.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+
+[[entry]]
+name = "nas"
+types = ["AAAA"]
+ipv6_suffix = "1::1234"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert!(matches!(
+            validate_config(&conf),
+            Err(ConfigError::InvalidIpv6Suffix(_, _, 64))
+        ));
+    }
+
+    #[test]
+    fn load_config_api_key_file() {
+        let dir = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&dir).expect("Failed to create test dir");
+        let key_file = dir.join("api-key");
+        fs::write(&key_file, "from-file\n").expect("Failed to write key file");
+        let temp = dir.join("test-11.toml");
+        fs::write(
+            &temp,
+            format!(
+                r#"
+fqdn = "example.com"
+api_key_file = {:?}
+
+[[entry]]
+name = "www"
+"#,
+                key_file.to_string_lossy()
+            ),
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert_eq!(conf.gandi.api_key, "from-file");
+    }
+
+    #[test]
+    fn load_config_api_key_env() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-12.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key_env = "GANDI_LIVE_DNS_TEST_API_KEY"
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to write test config file");
+        env::set_var("GANDI_LIVE_DNS_TEST_API_KEY", "from-env");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+
+        assert_eq!(conf.gandi.api_key, "from-env");
+    }
+
+    #[test]
+    fn find_api_key_credentials() {
+        let conf: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+
+[[entry]]
+name = "www"
+"#,
+        )
+        .expect("Failed to parse config");
+        let credentials = temp_dir().join("gandi-live-dns-test").join("credentials");
+        let secrets = temp_dir().join("gandi-live-dns-test").join("secrets");
+        fs::create_dir_all(&credentials).expect("Failed to create test dir");
+        fs::create_dir_all(&secrets).expect("Failed to create test dir");
+        let _ = fs::remove_file(credentials.join("gandi_api_key"));
+        fs::write(secrets.join("gandi_api_key"), "from-secret\n").expect("Failed to write secret");
+
+        assert_eq!(
+            find_api_key(
+                DEFAULT_PROVIDER,
+                &conf.gandi,
+                API_KEY_CREDENTIAL,
+                Some(&credentials),
+                &secrets
+            )
+            .unwrap(),
+            "from-secret"
+        );
+        fs::write(credentials.join("gandi_api_key"), "from-credential")
+            .expect("Failed to write credential");
+        assert_eq!(
+            find_api_key(
+                DEFAULT_PROVIDER,
+                &conf.gandi,
+                API_KEY_CREDENTIAL,
+                Some(&credentials),
+                &secrets
+            )
+            .unwrap(),
+            "from-credential"
+        );
+        assert!(matches!(
+            find_api_key(
+                DEFAULT_PROVIDER,
+                &conf.gandi,
+                API_KEY_CREDENTIAL,
+                None,
+                &temp_dir().join("gandi-live-dns-test").join("missing")
+            ),
+            Err(ConfigError::MissingApiKey(_, _))
+        ));
+    }
+
+    #[test]
+    fn load_config_static_records() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-13.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+
+[[entry]]
+name = "home"
+types = ["A", "AAAA"]
+
+[[entry]]
+name = "@"
+type = "TXT"
+values = ["v=spf1 -all"]
+
+[[entry]]
+name = "www"
+type = "CNAME"
+values = ["home"]
+
+[[entry]]
+name = "@"
+type = "MX"
+values = ["10 mail.example.com.", "20 backup-mail.example.com."]
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            skip_ipv4: true,
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+        validate_config(&conf).expect("Config should be valid");
+
+        assert_eq!(Config::types(&conf.entry[0]), vec!["AAAA"]);
+        assert_eq!(Config::values(&conf.entry[0]), None);
+        // Skipping IPv4 doesn't affect other types
+        assert_eq!(Config::types(&conf.entry[1]), vec!["TXT"]);
+        assert_eq!(
+            Config::values(&conf.entry[1]),
+            Some(vec!["\"v=spf1 -all\"".to_string()])
+        );
+        assert_eq!(
+            Config::values(&conf.entry[2]),
+            Some(vec!["home".to_string()])
+        );
+        assert_eq!(Config::values(&conf.entry[3]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn validate_config_invalid_static_records() {
+        let invalid = [
+            (
+                r#"type = "MX"
+values = ["mail.example.com."]"#,
+                "InvalidValue",
+            ),
+            (r#"type = "TXT""#, "MissingValues"),
+            (r#"values = ["hello"]"#, "MissingType"),
+            (
+                r#"type = "SRV"
+values = ["0 5 5060 sip.example.com."]"#,
+                "Validation",
+            ),
+        ];
+        for (entry, expected) in invalid {
+            let conf: Config = toml::from_str(&format!(
+                "fqdn = \"example.com\"\napi_key = \"yyy\"\n[[entry]]\nname = \"www\"\n{entry}\n"
+            ))
+            .expect("Failed to parse config");
+            let err = validate_config(&conf).expect_err("Config should be invalid");
+            assert!(format!("{err:?}").starts_with(expected), "{err:?}");
+        }
+
+        let cname_at_root: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+[[entry]]
+name = "@"
+type = "CNAME"
+values = ["home"]
+"#,
+        )
+        .expect("Failed to parse config");
+        assert!(matches!(
+            validate_config(&cname_at_root),
+            Err(ConfigError::InvalidCname(_))
+        ));
+    }
+
+    #[test]
+    fn find_zone_test() {
+        let conf: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+[[entry]]
+name = "@"
+[[entry]]
+name = "www"
+fqdn = "lab.example.com"
+"#,
+        )
+        .expect("Failed to parse config");
+        assert_eq!(
+            Config::find_zone(&conf, "_acme-challenge.www.example.com."),
+            Some(("example.com", "_acme-challenge.www".to_string()))
+        );
+        // The longest domain wins
+        assert_eq!(
+            Config::find_zone(&conf, "_acme-challenge.LAB.example.com"),
+            Some(("lab.example.com", "_acme-challenge".to_string()))
+        );
+        assert_eq!(
+            Config::find_zone(&conf, "example.com"),
+            Some(("example.com", "@".to_string()))
+        );
+        assert_eq!(Config::find_zone(&conf, "notexample.com"), None);
+    }
+
+    #[test]
+    fn load_config_providers() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        temp.push("test-14.toml");
+        fs::write(
+            &temp,
+            r#"
+fqdn = "example.com"
+
+[providers.work]
+type = "gandi"
+api_key = "zzz"
+auth_type = "Pat"
+
+[[entry]]
+name = "www"
+fqdn = "example.org"
+provider = "work"
+"#,
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        // No entry uses the default provider, so it doesn't need a key
+        let conf = load_config(&opts).expect("Failed to load config file");
+        validate_config(&conf).expect("Config should be valid");
+        let ProviderConfig::Gandi(work) = &conf.providers["work"] else {
+            panic!("Expected a Gandi provider");
+        };
+        assert_eq!(work.api_key, "zzz");
+        assert_eq!(work.api_url, "https://api.gandi.net");
+        assert_eq!(Config::provider(&conf.entry[0]), "work");
+        assert_eq!(Config::zone_provider(&conf, "example.org"), "work");
+        assert_eq!(
+            Config::zone_provider(&conf, "example.com"),
+            DEFAULT_PROVIDER
+        );
+
+        let unknown: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "yyy"
+[[entry]]
+name = "www"
+provider = "home"
+"#,
+        )
+        .expect("Failed to parse config");
+        assert!(matches!(
+            validate_config(&unknown),
+            Err(ConfigError::UnknownProvider(_, _))
+        ));
+    }
+
+    #[test]
+    fn load_config_rfc2136() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        let secret_file = temp.join("test-15-secret");
+        fs::write(&secret_file, "bm90IGEgdmVyeSBzZWNyZXQgc2VjcmV0\n")
+            .expect("Failed to write test secret file");
+        temp.push("test-15.toml");
+        fs::write(
+            &temp,
+            format!(
+                r#"
+fqdn = "example.com"
+
+[providers.home]
+type = "rfc2136"
+server = "ns1.example.com"
+key_name = "update-key"
+key_secret_file = "{}"
+
+[[entry]]
+name = "www"
+provider = "home"
+"#,
+                secret_file.to_string_lossy()
+            ),
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = load_config(&opts).expect("Failed to load config file");
+        validate_config(&conf).expect("Config should be valid");
+        let ProviderConfig::Rfc2136(home) = &conf.providers["home"] else {
+            panic!("Expected an RFC 2136 provider");
+        };
+        assert_eq!(home.server, "ns1.example.com");
+        assert_eq!(home.port, 53);
+        assert_eq!(home.key_secret, "bm90IGEgdmVyeSBzZWNyZXQgc2VjcmV0");
+
+        let invalid: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+[providers.home]
+type = "rfc2136"
+server = "ns1.example.com"
+key_name = "update-key"
+key_secret = "not base64!"
+[[entry]]
+name = "www"
+provider = "home"
+"#,
+        )
+        .expect("Failed to parse config");
+        assert!(matches!(
+            validate_config(&invalid),
+            Err(ConfigError::InvalidSecret(_, _))
+        ));
+    }
+
+    #[test]
+    fn validate_config_dyndns2_state() {
+        let mut conf: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+[providers.noip]
+type = "dyndns2"
+url = "https://dynupdate.no-ip.com/nic/update"
+username = "user"
+password = "pass"
+[[entry]]
+name = "home"
+provider = "noip"
+"#,
+        )
+        .expect("Failed to parse config");
+        assert!(matches!(
+            validate_config(&conf),
+            Err(ConfigError::Dyndns2WithoutState(_))
+        ));
+        conf.state = true;
+        validate_config(&conf).expect("Config should be valid");
+    }
+
+    #[test]
+    fn load_config_serve() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp).expect("Failed to create test dir");
+        let password_file = temp.join("test-16-password");
+        fs::write(&password_file, "hunter2\n").expect("Failed to write test password file");
+        temp.push("test-16.toml");
+        fs::write(
+            &temp,
+            format!(
+                r#"
+fqdn = "example.com"
+api_key = "xxx"
+
+[serve]
+listen = "127.0.0.1:8080"
+trusted_proxies = ["127.0.0.1"]
+
+[[serve.host]]
+hostname = "home.example.com"
+username = "router"
+password_file = "{}"
+"#,
+                password_file.to_string_lossy()
+            ),
+        )
+        .expect("Failed to write test config file");
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        // Entries are optional when only serving
+        let conf = load_config(&opts).expect("Failed to load config file");
+        validate_config(&conf).expect("Config should be valid");
+        let serve = conf.serve.expect("Serve section is missing");
+        assert_eq!(serve.listen.to_string(), "127.0.0.1:8080");
+        assert_eq!(serve.trusted_proxies, vec![IpAddr::from([127, 0, 0, 1])]);
+        assert_eq!(serve.hosts[0].password, "hunter2");
+
+        let unknown: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "xxx"
+[[serve.host]]
+hostname = "home.example.org"
+username = "router"
+password = "hunter2"
+"#,
+        )
+        .expect("Failed to parse config");
+        assert!(matches!(
+            validate_config(&unknown),
+            Err(ConfigError::UnknownServeHost(_))
+        ));
+    }
 }
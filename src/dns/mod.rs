@@ -0,0 +1,465 @@
+//! A minimal implementation of the DNS wire format, just enough to send
+//! queries and dynamic updates without pulling in a full resolver.
+
+pub(crate) mod tsig;
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::timeout};
+
+use tsig::TsigKey;
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_CNAME: u16 = 5;
+pub(crate) const TYPE_SOA: u16 = 6;
+pub(crate) const TYPE_MX: u16 = 15;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_TSIG: u16 = 250;
+pub(crate) const TYPE_CAA: u16 = 257;
+pub(crate) const CLASS_IN: u16 = 1;
+/// Used to delete a whole RRset in updates, and for TSIG records.
+pub(crate) const CLASS_ANY: u16 = 255;
+
+/// The response code for a name that doesn't exist.
+pub(crate) const RCODE_NXDOMAIN: u16 = 3;
+
+/// Recursion desired.
+const FLAG_RD: u16 = 0x0100;
+/// The UPDATE opcode, see RFC 2136.
+const OPCODE_UPDATE: u16 = 5 << 11;
+/// The message is a response.
+const FLAG_QR: u16 = 0x8000;
+/// The response was truncated.
+const FLAG_TC: u16 = 0x0200;
+
+/// How long to wait for the server to respond.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest UDP response we accept, see RFC 6891.
+const MAX_UDP_SIZE: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum DnsError {
+    #[error("Failed to talk to the DNS server: {0}")]
+    Io(#[from] io::Error),
+    #[error("DNS server did not respond in time")]
+    Timeout(),
+    #[error("DNS server sent a malformed message: {0}")]
+    Malformed(String),
+    #[error("DNS server responded with error code {0} ({})", rcode_name(*.0))]
+    Rcode(u16),
+    #[error("DNS response was truncated")]
+    Truncated(),
+    #[error("The TSIG signature of the DNS response is not valid: {0}")]
+    Tsig(String),
+}
+
+fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown",
+    }
+}
+
+/// The type code for a record type name, for the types this tool can publish.
+pub(crate) fn type_code(name: &str) -> Option<u16> {
+    Some(match name {
+        "A" => TYPE_A,
+        "CNAME" => TYPE_CNAME,
+        "MX" => TYPE_MX,
+        "TXT" => TYPE_TXT,
+        "AAAA" => TYPE_AAAA,
+        "CAA" => TYPE_CAA,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Question {
+    pub(crate) name: String,
+    pub(crate) qtype: u16,
+    pub(crate) qclass: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) name: String,
+    pub(crate) rtype: u16,
+    pub(crate) class: u16,
+    pub(crate) ttl: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Message {
+    pub(crate) id: u16,
+    pub(crate) flags: u16,
+    pub(crate) questions: Vec<Question>,
+    pub(crate) answers: Vec<Record>,
+    pub(crate) authority: Vec<Record>,
+    pub(crate) additional: Vec<Record>,
+}
+
+pub(crate) fn encode_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+fn encode_record(buf: &mut Vec<u8>, record: &Record) {
+    encode_name(buf, &record.name);
+    buf.extend_from_slice(&record.rtype.to_be_bytes());
+    buf.extend_from_slice(&record.class.to_be_bytes());
+    buf.extend_from_slice(&record.ttl.to_be_bytes());
+    buf.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&record.data);
+}
+
+/// Reads data out of a message, keeping track of where we are.
+pub(crate) struct Reader<'m> {
+    message: &'m [u8],
+    offset: usize,
+}
+
+impl<'m> Reader<'m> {
+    pub(crate) fn new(message: &'m [u8]) -> Reader<'m> {
+        Reader { message, offset: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset >= self.message.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'m [u8], DnsError> {
+        let bytes = self
+            .message
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| DnsError::Malformed("message ended unexpectedly".to_string()))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DnsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DnsError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DnsError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name.
+    pub(crate) fn name(&mut self) -> Result<String, DnsError> {
+        let mut labels: Vec<String> = Vec::new();
+        // Where to continue reading after following compression pointers
+        let mut resume: Option<usize> = None;
+        // Protects against pointer loops
+        let mut jumps = 0;
+        loop {
+            let len = self.u8()?;
+            match len & 0xc0 {
+                0x00 if len == 0 => break,
+                0x00 => {
+                    let label = self.bytes(len as usize)?;
+                    labels.push(String::from_utf8_lossy(label).to_string());
+                }
+                0xc0 => {
+                    let pointer = (((len & 0x3f) as usize) << 8) | self.u8()? as usize;
+                    jumps += 1;
+                    if jumps > 64 {
+                        return Err(DnsError::Malformed("compression loop".to_string()));
+                    }
+                    resume.get_or_insert(self.offset);
+                    self.offset = pointer;
+                }
+                _ => return Err(DnsError::Malformed("unknown label type".to_string())),
+            }
+        }
+        if let Some(resume) = resume {
+            self.offset = resume;
+        }
+        Ok(labels.join("."))
+    }
+
+    fn record(&mut self) -> Result<Record, DnsError> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let start = self.offset;
+        let data = match rtype {
+            // Names in these may be compressed, which only means something
+            // within the whole message, so keep them expanded
+            TYPE_CNAME => {
+                let mut data = Vec::new();
+                encode_name(&mut data, &self.name()?);
+                data
+            }
+            TYPE_MX => {
+                let mut data = self.u16()?.to_be_bytes().to_vec();
+                encode_name(&mut data, &self.name()?);
+                data
+            }
+            _ => self.bytes(len)?.to_vec(),
+        };
+        if self.offset != start + len {
+            return Err(DnsError::Malformed(format!(
+                "record data of type {rtype} has the wrong length"
+            )));
+        }
+        Ok(Record {
+            name,
+            rtype,
+            class,
+            ttl,
+            data,
+        })
+    }
+}
+
+impl Message {
+    pub(crate) fn query(id: u16, name: &str, qtype: u16) -> Message {
+        Message {
+            id,
+            flags: FLAG_RD,
+            questions: vec![Question {
+                name: name.to_string(),
+                qtype,
+                qclass: CLASS_IN,
+            }],
+            ..Message::default()
+        }
+    }
+
+    /// An update to the zone, with the changes in the update section. The
+    /// update section is sent as the authority section, see RFC 2136.
+    pub(crate) fn update(id: u16, zone: &str, updates: Vec<Record>) -> Message {
+        Message {
+            id,
+            flags: OPCODE_UPDATE,
+            questions: vec![Question {
+                name: zone.to_string(),
+                qtype: TYPE_SOA,
+                qclass: CLASS_IN,
+            }],
+            authority: updates,
+            ..Message::default()
+        }
+    }
+
+    pub(crate) fn rcode(&self) -> u16 {
+        self.flags & 0x000f
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len(),
+        ] {
+            buf.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        for question in &self.questions {
+            encode_name(&mut buf, &question.name);
+            buf.extend_from_slice(&question.qtype.to_be_bytes());
+            buf.extend_from_slice(&question.qclass.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            encode_record(&mut buf, record);
+        }
+        buf
+    }
+
+    pub(crate) fn decode(message: &[u8]) -> Result<Message, DnsError> {
+        let mut reader = Reader::new(message);
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let questions = reader.u16()?;
+        let answers = reader.u16()?;
+        let authority = reader.u16()?;
+        let additional = reader.u16()?;
+        Ok(Message {
+            id,
+            flags,
+            questions: (0..questions)
+                .map(|_| {
+                    Ok(Question {
+                        name: reader.name()?,
+                        qtype: reader.u16()?,
+                        qclass: reader.u16()?,
+                    })
+                })
+                .collect::<Result<_, DnsError>>()?,
+            answers: (0..answers)
+                .map(|_| reader.record())
+                .collect::<Result<_, _>>()?,
+            authority: (0..authority)
+                .map(|_| reader.record())
+                .collect::<Result<_, _>>()?,
+            additional: (0..additional)
+                .map(|_| reader.record())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Splits the data of a TXT record into its strings.
+pub(crate) fn txt_strings(data: &[u8]) -> Result<Vec<String>, DnsError> {
+    let mut reader = Reader::new(data);
+    let mut strings = Vec::new();
+    while !reader.is_empty() {
+        let len = reader.u8()? as usize;
+        strings.push(String::from_utf8_lossy(reader.bytes(len)?).to_string());
+    }
+    Ok(strings)
+}
+
+/// Sends the message to the server over UDP, and waits for the response.
+/// Returns the response as it was received along with the decoded one.
+async fn send(server: SocketAddr, id: u16, bytes: &[u8]) -> Result<(Vec<u8>, Message), DnsError> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(bytes).await?;
+
+    let mut buf = vec![0u8; MAX_UDP_SIZE];
+    loop {
+        let len = timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+            .await
+            .map_err(|_| DnsError::Timeout())??;
+        let response = Message::decode(&buf[..len])?;
+        // Ignore anything that isn't the response to our message
+        if response.id != id || response.flags & FLAG_QR == 0 {
+            continue;
+        }
+        if response.flags & FLAG_TC != 0 {
+            return Err(DnsError::Truncated());
+        }
+        buf.truncate(len);
+        return Ok((buf, response));
+    }
+}
+
+/// Sends the message to the server, and waits for the response.
+pub(crate) async fn exchange(server: SocketAddr, message: &Message) -> Result<Message, DnsError> {
+    let (_, response) = send(server, message.id, &message.encode()).await?;
+    if response.rcode() != 0 {
+        return Err(DnsError::Rcode(response.rcode()));
+    }
+    Ok(response)
+}
+
+/// Signs the message with the key, sends it to the server, and checks that
+/// the response is signed with the same key.
+pub(crate) async fn exchange_signed(
+    server: SocketAddr,
+    message: &Message,
+    key: &TsigKey,
+) -> Result<Message, DnsError> {
+    let (bytes, mac) = key.sign(message, tsig::now());
+    let (raw, response) = send(server, message.id, &bytes).await?;
+    // Servers can't sign errors about the key itself, and a forged error
+    // doesn't change anything, so only successful responses are checked
+    if response.rcode() != 0 {
+        return Err(DnsError::Rcode(response.rcode()));
+    }
+    key.verify(&raw, &mac, tsig::now())?;
+    Ok(response)
+}
+
+/// Asks the server for the records of this name and type.
+pub(crate) async fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+) -> Result<Vec<Record>, DnsError> {
+    let message = Message::query(rand::random(), name, qtype);
+    let response = exchange(server, &message).await?;
+    Ok(response
+        .answers
+        .into_iter()
+        .filter(|record| record.rtype == qtype)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{txt_strings, Message, Record, CLASS_IN, TYPE_A, TYPE_TXT};
+
+    #[test]
+    fn encode_decode_test() {
+        let mut message = Message::query(0x1234, "myip.opendns.com.", TYPE_A);
+        message.answers.push(Record {
+            name: "myip.opendns.com".to_string(),
+            rtype: TYPE_A,
+            class: CLASS_IN,
+            ttl: 0,
+            data: vec![93, 184, 216, 34],
+        });
+        let decoded = Message::decode(&message.encode()).expect("Failed to decode message");
+        assert_eq!(decoded.id, 0x1234);
+        assert_eq!(decoded.questions[0].name, "myip.opendns.com");
+        assert_eq!(decoded.answers, message.answers);
+    }
+
+    #[test]
+    fn decode_compressed_name_test() {
+        let mut bytes = Message::query(1, "example.com", TYPE_TXT).encode();
+        // Bump the answer count, then add an answer that points to the name
+        // in the question.
+        bytes[7] = 1;
+        bytes.extend_from_slice(&[0xc0, 12]);
+        bytes.extend_from_slice(&TYPE_TXT.to_be_bytes());
+        bytes.extend_from_slice(&CLASS_IN.to_be_bytes());
+        bytes.extend_from_slice(&60u32.to_be_bytes());
+        bytes.extend_from_slice(&6u16.to_be_bytes());
+        bytes.extend_from_slice(&[2, b'h', b'i', 2, b'y', b'o']);
+
+        let decoded = Message::decode(&bytes).expect("Failed to decode message");
+        assert_eq!(decoded.answers[0].name, "example.com");
+        assert_eq!(
+            txt_strings(&decoded.answers[0].data).unwrap(),
+            vec!["hi".to_string(), "yo".to_string()]
+        );
+    }
+
+    #[test]
+    fn decode_truncated_message_test() {
+        let bytes = Message::query(1, "example.com", TYPE_A).encode();
+        assert!(Message::decode(&bytes[..bytes.len() - 2]).is_err());
+    }
+}
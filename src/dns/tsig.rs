@@ -0,0 +1,269 @@
+//! Transaction signatures for DNS messages, see RFC 8945. Only HMAC-SHA256 is
+//! supported, which is what BIND and Knot generate keys for by default.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::hmac;
+
+use super::{encode_name, DnsError, Message, Reader, Record, CLASS_ANY, TYPE_TSIG};
+
+const ALGORITHM: &str = "hmac-sha256";
+/// How far apart our clock and the server's may be, in seconds.
+const FUDGE: u16 = 300;
+
+/// The TSIG error codes, see RFC 8945 section 3.
+fn error_name(error: u16) -> &'static str {
+    match error {
+        16 => "BADSIG",
+        17 => "BADKEY",
+        18 => "BADTIME",
+        22 => "BADTRUNC",
+        _ => "unknown",
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+pub(crate) struct TsigKey {
+    name: String,
+    key: hmac::Key,
+}
+
+/// The fields of a TSIG record, other than the MAC, that are signed.
+struct Variables<'v> {
+    key_name: &'v str,
+    time: u64,
+    fudge: u16,
+    error: u16,
+    other: &'v [u8],
+}
+
+impl Variables<'_> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        // Names are signed in their canonical form, which is lowercase
+        encode_name(buf, &self.key_name.to_ascii_lowercase());
+        buf.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        buf.extend_from_slice(&0u32.to_be_bytes());
+        encode_name(buf, ALGORITHM);
+        encode_time(buf, self.time);
+        buf.extend_from_slice(&self.fudge.to_be_bytes());
+        buf.extend_from_slice(&self.error.to_be_bytes());
+        buf.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        buf.extend_from_slice(self.other);
+    }
+}
+
+/// Times are 48 bit numbers of seconds.
+fn encode_time(buf: &mut Vec<u8>, time: u64) {
+    buf.extend_from_slice(&time.to_be_bytes()[2..]);
+}
+
+impl TsigKey {
+    pub(crate) fn new(name: &str, secret: &[u8]) -> TsigKey {
+        TsigKey {
+            name: name.trim_end_matches('.').to_string(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    /// Signs the message, returning it with the signature added, and the MAC
+    /// that is needed to check the response.
+    pub(crate) fn sign(&self, message: &Message, time: u64) -> (Vec<u8>, Vec<u8>) {
+        self.sign_with_prefix(message, &[], time)
+    }
+
+    /// Signs a response to a request with this MAC, the way a server does.
+    #[cfg(test)]
+    pub(crate) fn sign_response(
+        &self,
+        response: &Message,
+        request_mac: &[u8],
+        time: u64,
+    ) -> Vec<u8> {
+        let mut prefix = (request_mac.len() as u16).to_be_bytes().to_vec();
+        prefix.extend_from_slice(request_mac);
+        self.sign_with_prefix(response, &prefix, time).0
+    }
+
+    fn sign_with_prefix(&self, message: &Message, prefix: &[u8], time: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut data = prefix.to_vec();
+        data.extend_from_slice(&message.encode());
+        Variables {
+            key_name: &self.name,
+            time,
+            fudge: FUDGE,
+            error: 0,
+            other: &[],
+        }
+        .encode(&mut data);
+        let mac = hmac::sign(&self.key, &data).as_ref().to_vec();
+
+        let mut rdata = Vec::new();
+        encode_name(&mut rdata, ALGORITHM);
+        encode_time(&mut rdata, time);
+        rdata.extend_from_slice(&FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&message.id.to_be_bytes());
+        // No error, and no other data
+        rdata.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut signed = message.clone();
+        signed.additional.push(Record {
+            name: self.name.clone(),
+            rtype: TYPE_TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            data: rdata,
+        });
+        (signed.encode(), mac)
+    }
+
+    /// Checks the signature of a response, given the MAC of the request it
+    /// answers. The response has to be checked as it was received, because
+    /// the signature covers the exact bytes, compression and all.
+    pub(crate) fn verify(
+        &self,
+        response: &[u8],
+        request_mac: &[u8],
+        now: u64,
+    ) -> Result<(), DnsError> {
+        // The signature is always the last record in the message
+        let mut reader = Reader::new(response);
+        reader.bytes(4)?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+        if counts[3] == 0 {
+            return Err(DnsError::Tsig("the response is not signed".to_string()));
+        }
+        for _ in 0..counts[0] {
+            reader.name()?;
+            reader.bytes(4)?;
+        }
+        let records = counts[1..]
+            .iter()
+            .map(|&count| count as usize)
+            .sum::<usize>();
+        for _ in 0..records - 1 {
+            reader.record()?;
+        }
+        let tsig_start = reader.offset;
+        let tsig = reader.record()?;
+        if tsig.rtype != TYPE_TSIG {
+            return Err(DnsError::Tsig("the response is not signed".to_string()));
+        }
+        if !tsig.name.eq_ignore_ascii_case(&self.name) {
+            return Err(DnsError::Tsig(format!("signed with key '{}'", tsig.name)));
+        }
+
+        let mut rdata = Reader::new(&tsig.data);
+        let algorithm = rdata.name()?;
+        if !algorithm.eq_ignore_ascii_case(ALGORITHM) {
+            return Err(DnsError::Tsig(format!("signed with {algorithm}")));
+        }
+        let time_bytes = rdata.bytes(6)?;
+        let mut time = [0u8; 8];
+        time[2..].copy_from_slice(time_bytes);
+        let time = u64::from_be_bytes(time);
+        let fudge = rdata.u16()?;
+        let mac_len = rdata.u16()? as usize;
+        let mac = rdata.bytes(mac_len)?;
+        let original_id = rdata.u16()?;
+        let error = rdata.u16()?;
+        let other_len = rdata.u16()? as usize;
+        let other = rdata.bytes(other_len)?;
+        if error != 0 {
+            return Err(DnsError::Tsig(format!(
+                "the server reported {}",
+                error_name(error)
+            )));
+        }
+
+        // Sign the response as it was before the signature was added
+        let mut data = Vec::new();
+        data.extend_from_slice(&(request_mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(request_mac);
+        data.extend_from_slice(&original_id.to_be_bytes());
+        data.extend_from_slice(&response[2..10]);
+        data.extend_from_slice(&(counts[3] - 1).to_be_bytes());
+        data.extend_from_slice(&response[12..tsig_start]);
+        Variables {
+            key_name: &tsig.name,
+            time,
+            fudge,
+            error,
+            other,
+        }
+        .encode(&mut data);
+        hmac::verify(&self.key, &data, mac)
+            .map_err(|_| DnsError::Tsig("the signature doesn't match".to_string()))?;
+        if now.abs_diff(time) > u64::from(fudge) {
+            return Err(DnsError::Tsig(
+                "the server's clock is too far from ours".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TsigKey;
+    use crate::dns::{Message, Record, CLASS_IN, TYPE_A};
+
+    const SECRET: &[u8] = b"not a very secret secret";
+
+    #[test]
+    fn sign_test() {
+        let key = TsigKey::new("update-key.", SECRET);
+        let message = Message::update(
+            0x1234,
+            "example.com",
+            vec![Record {
+                name: "www.example.com".to_string(),
+                rtype: TYPE_A,
+                class: CLASS_IN,
+                ttl: 300,
+                data: vec![93, 184, 216, 34],
+            }],
+        );
+        let (signed, mac) = key.sign(&message, 1_700_000_000);
+        // Computed separately, with the message and TSIG variables put
+        // together by hand and Python's hmac module
+        assert_eq!(
+            mac.iter().map(|b| format!("{b:02x}")).collect::<String>(),
+            "0cb2364885bd7d0c03fd411ff9cd6965377baecc57bf729ccea96aa1d73036ae"
+        );
+        let decoded = Message::decode(&signed).expect("Failed to decode signed message");
+        assert_eq!(decoded.additional.len(), 1);
+        assert_eq!(decoded.additional[0].name, "update-key");
+    }
+
+    #[test]
+    fn verify_test() {
+        let key = TsigKey::new("update-key", SECRET);
+        let request = Message::update(0x1234, "example.com", vec![]);
+        let (_, request_mac) = key.sign(&request, 1_700_000_000);
+
+        let response = Message {
+            flags: 0xa800,
+            ..request.clone()
+        };
+        let signed = key.sign_response(&response, &request_mac, 1_700_000_010);
+        key.verify(&signed, &request_mac, 1_700_000_020)
+            .expect("Response should be valid");
+
+        assert!(key.verify(&signed, &[0; 32], 1_700_000_020).is_err());
+        assert!(key.verify(&signed, &request_mac, 1_700_010_000).is_err());
+        assert!(TsigKey::new("update-key", b"other")
+            .verify(&signed, &request_mac, 1_700_000_020)
+            .is_err());
+        let unsigned = response.encode();
+        assert!(key.verify(&unsigned, &request_mac, 1_700_000_020).is_err());
+    }
+}
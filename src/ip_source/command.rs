@@ -0,0 +1,149 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::{process::Command, time::timeout};
+
+use crate::ClientError;
+
+use super::common::{find_ip, parse_ipv4, parse_ipv6, IPSource};
+
+/// How long the command may run before it is killed.
+pub(crate) const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs a command, and reads the IP address from what it prints. If the
+/// command prints multiple lines, the first one that is an address of the
+/// requested IP version is used.
+pub(crate) struct IPSourceCommand {
+    pub(crate) command: String,
+    pub(crate) timeout: Duration,
+}
+
+impl IPSourceCommand {
+    async fn run(&self, ip_version: &str) -> Result<String, ClientError> {
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        };
+        #[cfg(not(windows))]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        command
+            .arg(&self.command)
+            // Lets the command print only the address that was asked for
+            .env("GANDI_LIVE_DNS_IP_VERSION", ip_version)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+
+        let output = timeout(self.timeout, command.output())
+            .await
+            .map_err(|_| {
+                ClientError::Command(
+                    self.command.clone(),
+                    format!("timed out after {} seconds", self.timeout.as_secs()),
+                )
+            })?
+            .map_err(|err| ClientError::Command(self.command.clone(), err.to_string()))?;
+        if !output.status.success() {
+            return Err(ClientError::Command(
+                self.command.clone(),
+                format!(
+                    "{}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceCommand {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        find_ip(self.run("4").await?.lines(), parse_ipv4)
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        find_ip(self.run("6").await?.lines(), parse_ipv6)
+    }
+    fn name(&self) -> String {
+        format!("command '{}'", self.command)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::time::Duration;
+
+    use super::{IPSourceCommand, COMMAND_TIMEOUT};
+    use crate::{ip_source::common::IPSource, ClientError};
+
+    fn source(command: &str) -> IPSourceCommand {
+        IPSourceCommand {
+            command: command.to_string(),
+            timeout: COMMAND_TIMEOUT,
+        }
+    }
+
+    #[tokio::test]
+    async fn output_test() {
+        let source = source("printf '93.184.216.34\\n2606:4700::1111\\n'");
+        assert_eq!(
+            source
+                .get_ipv4()
+                .await
+                .expect("Failed to get the IP address"),
+            Ipv4Addr::new(93, 184, 216, 34)
+        );
+        assert_eq!(
+            source
+                .get_ipv6()
+                .await
+                .expect("Failed to get the IP address"),
+            "2606:4700::1111".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn ip_version_env_test() {
+        let source = source(
+            "if [ \"$GANDI_LIVE_DNS_IP_VERSION\" = 4 ]; then echo 93.184.216.34; else echo 2606:4700::1111; fi",
+        );
+        assert_eq!(
+            source
+                .get_ipv4()
+                .await
+                .expect("Failed to get the IP address"),
+            Ipv4Addr::new(93, 184, 216, 34)
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_output_test() {
+        let result = source("echo 'not an address'").get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::InvalidIp(_))));
+    }
+
+    #[tokio::test]
+    async fn failure_test() {
+        let result = source("echo 93.184.216.34; exit 1").get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::Command(_, _))));
+    }
+
+    #[tokio::test]
+    async fn timeout_test() {
+        let source = IPSourceCommand {
+            command: "sleep 10".to_string(),
+            timeout: Duration::from_millis(100),
+        };
+        let result = source.get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::Command(_, _))));
+    }
+}
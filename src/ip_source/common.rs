@@ -1,9 +1,219 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
 use async_trait::async_trait;
+use tokio::net::lookup_host;
 
 use crate::ClientError;
 
 #[async_trait]
-pub trait IPSource {
-    async fn get_ipv4(&self) -> Result<String, ClientError>;
-    async fn get_ipv6(&self) -> Result<String, ClientError>;
+pub trait IPSource: Send + Sync {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError>;
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError>;
+    /// Describes the source, to report where an address came from.
+    fn name(&self) -> String;
+
+    /// Like `get_ipv4`, but also returns the name of the source that found
+    /// the address. Sources that combine other sources name the ones used.
+    async fn get_ipv4_from(&self) -> Result<(Ipv4Addr, String), ClientError> {
+        Ok((self.get_ipv4().await?, self.name()))
+    }
+    async fn get_ipv6_from(&self) -> Result<(Ipv6Addr, String), ClientError> {
+        Ok((self.get_ipv6().await?, self.name()))
+    }
+}
+
+/// Finds an address for the server in the requested IP version. Sources that
+/// learn the address from the server seeing our packets need this, as the
+/// server can only tell us the address of the IP version we used.
+pub(crate) async fn lookup_server(
+    host: &str,
+    port: u16,
+    ipv4: bool,
+) -> Result<SocketAddr, ClientError> {
+    let family = if ipv4 { "IPv4" } else { "IPv6" };
+    lookup_host((host, port))
+        .await
+        .map_err(|err| {
+            ClientError::ServerLookup(host.to_string(), family.to_string(), err.to_string())
+        })?
+        .find(|addr| addr.is_ipv4() == ipv4)
+        .ok_or_else(|| {
+            ClientError::ServerLookup(
+                host.to_string(),
+                family.to_string(),
+                "no address found".to_string(),
+            )
+        })
+}
+
+/// How much of a bad response to include in errors. Enough to recognize an
+/// error page, without dumping the whole thing.
+const MAX_INVALID_RESPONSE_LEN: usize = 64;
+
+fn parse_ip(text: &str) -> Result<IpAddr, ClientError> {
+    let text = text.trim();
+    text.parse()
+        .map_err(|_| ClientError::InvalidIp(text.chars().take(MAX_INVALID_RESPONSE_LEN).collect()))
+}
+
+/// Parses the response of an IP source, making sure that it is a public IPv4
+/// address.
+pub(crate) fn parse_ipv4(text: &str) -> Result<Ipv4Addr, ClientError> {
+    match parse_ip(text)? {
+        IpAddr::V4(ip) => public_ipv4(ip),
+        ip => Err(ClientError::WrongIpFamily("IPv4".to_string(), ip)),
+    }
+}
+
+/// Parses the response of an IP source, making sure that it is a public IPv6
+/// address.
+pub(crate) fn parse_ipv6(text: &str) -> Result<Ipv6Addr, ClientError> {
+    match parse_ip(text)? {
+        IpAddr::V6(ip) => public_ipv6(ip),
+        ip => Err(ClientError::WrongIpFamily("IPv6".to_string(), ip)),
+    }
+}
+
+/// Returns the first candidate that is a valid address, for sources that may
+/// respond with more than one address or other information alongside it. If
+/// none of them are valid, the error for the first one is returned.
+pub(crate) fn find_ip<'t, T, I, F>(candidates: I, parse: F) -> Result<T, ClientError>
+where
+    I: IntoIterator<Item = &'t str>,
+    F: Fn(&str) -> Result<T, ClientError>,
+{
+    let mut first_error: Option<ClientError> = None;
+    for candidate in candidates {
+        if candidate.trim().is_empty() {
+            continue;
+        }
+        match parse(candidate) {
+            Ok(ip) => return Ok(ip),
+            Err(err) => {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| ClientError::InvalidIp(String::new())))
+}
+
+/// Makes sure that an address an IP source found is a public address.
+pub(crate) fn public_ipv4(ip: Ipv4Addr) -> Result<Ipv4Addr, ClientError> {
+    if is_public_ipv4(&ip) {
+        Ok(ip)
+    } else {
+        Err(ClientError::NonPublicIp(IpAddr::V4(ip)))
+    }
+}
+
+/// Makes sure that an address an IP source found is a public address.
+pub(crate) fn public_ipv6(ip: Ipv6Addr) -> Result<Ipv6Addr, ClientError> {
+    if is_public_ipv6(&ip) {
+        Ok(ip)
+    } else {
+        Err(ClientError::NonPublicIp(IpAddr::V6(ip)))
+    }
+}
+
+pub(crate) async fn get_ip(api_url: &str) -> Result<String, ClientError> {
+    let response = reqwest::get(api_url).await?;
+    let text = response.text().await?;
+    Ok(text)
+}
+
+/// Whether the address is publicly routable, i.e. not private, loopback,
+/// link-local, CGNAT or otherwise reserved.
+pub(crate) fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8, "this network"
+        || first == 0
+        // 100.64.0.0/10, shared address space used by carrier-grade NAT
+        || (first == 100 && (second & 0b1100_0000) == 64)
+        // 240.0.0.0/4, reserved for future use
+        || first >= 240)
+}
+
+/// Whether the address is a global unicast address. This excludes loopback,
+/// link-local, unique local (ULA), multicast and documentation addresses.
+pub(crate) fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // 2000::/3 is the only range currently allocated for global unicast
+    (segments[0] & 0xe000) == 0x2000
+        // 2001:db8::/32 is reserved for documentation
+        && !(segments[0] == 0x2001 && segments[1] == 0x0db8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::{is_public_ipv4, is_public_ipv6, parse_ipv4, parse_ipv6};
+    use crate::ClientError;
+
+    #[test]
+    fn parse_ipv4_test() {
+        assert_eq!(
+            parse_ipv4("93.184.216.34\n").unwrap(),
+            Ipv4Addr::new(93, 184, 216, 34)
+        );
+        assert!(matches!(
+            parse_ipv4("<html><body>Please log in</body></html>"),
+            Err(ClientError::InvalidIp(_))
+        ));
+        assert!(matches!(
+            parse_ipv4("2606:4700::1111"),
+            Err(ClientError::WrongIpFamily(_, _))
+        ));
+        assert!(matches!(
+            parse_ipv4("192.168.1.1"),
+            Err(ClientError::NonPublicIp(_))
+        ));
+        assert!(matches!(
+            parse_ipv4("100.64.12.1"),
+            Err(ClientError::NonPublicIp(_))
+        ));
+    }
+
+    #[test]
+    fn parse_ipv6_test() {
+        assert_eq!(
+            parse_ipv6(" 2606:4700::1111 ").unwrap(),
+            "2606:4700::1111".parse::<Ipv6Addr>().unwrap()
+        );
+        assert!(matches!(
+            parse_ipv6("93.184.216.34"),
+            Err(ClientError::WrongIpFamily(_, _))
+        ));
+        assert!(matches!(
+            parse_ipv6("::1"),
+            Err(ClientError::NonPublicIp(_))
+        ));
+    }
+
+    #[test]
+    fn public_ipv4_test() {
+        assert!(is_public_ipv4(&Ipv4Addr::new(1, 1, 1, 1)));
+        assert!(is_public_ipv4(&Ipv4Addr::new(100, 128, 0, 1)));
+        assert!(!is_public_ipv4(&Ipv4Addr::new(192, 168, 0, 1)));
+        assert!(!is_public_ipv4(&Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(!is_public_ipv4(&Ipv4Addr::new(127, 0, 0, 1)));
+        assert!(!is_public_ipv4(&Ipv4Addr::new(100, 64, 0, 1)));
+        assert!(!is_public_ipv4(&Ipv4Addr::new(169, 254, 1, 1)));
+    }
+
+    #[test]
+    fn public_ipv6_test() {
+        assert!(is_public_ipv6(&"2606:4700::1111".parse().unwrap()));
+        assert!(!is_public_ipv6(&Ipv6Addr::LOCALHOST));
+        assert!(!is_public_ipv6(&"fe80::1".parse().unwrap()));
+        assert!(!is_public_ipv6(&"fd00::1".parse().unwrap()));
+        assert!(!is_public_ipv6(&"2001:db8::1".parse().unwrap()));
+    }
 }
@@ -0,0 +1,178 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use async_trait::async_trait;
+use regex::Regex;
+
+use crate::config::{ConfigError, CustomExtract, CustomIPSource};
+use crate::ClientError;
+
+use super::common::{get_ip, parse_ipv4, parse_ipv6, IPSource};
+
+enum Extractor {
+    Text,
+    Regex(Regex),
+    JsonPointer(String),
+}
+
+/// An IP source that queries an HTTP service configured by the user.
+pub(crate) struct IPSourceCustom {
+    ipv4_url: Option<String>,
+    ipv6_url: Option<String>,
+    extractor: Extractor,
+}
+
+impl IPSourceCustom {
+    pub(crate) fn new(config: &CustomIPSource) -> Result<Self, ConfigError> {
+        let extractor = match &config.extract {
+            CustomExtract::Text => Extractor::Text,
+            CustomExtract::Regex(regex) => Extractor::Regex(Regex::new(regex)?),
+            CustomExtract::JsonPointer(pointer) => Extractor::JsonPointer(pointer.clone()),
+        };
+        Ok(IPSourceCustom {
+            ipv4_url: config.ipv4_url.clone(),
+            ipv6_url: config.ipv6_url.clone(),
+            extractor,
+        })
+    }
+
+    fn extract(&self, response: &str) -> Result<String, ClientError> {
+        match &self.extractor {
+            Extractor::Text => Ok(response.trim().to_string()),
+            Extractor::Regex(regex) => regex
+                .captures(response)
+                .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+                .map(|found| found.as_str().trim().to_string())
+                .ok_or_else(|| {
+                    ClientError::CustomExtract(format!(
+                        "regex '{regex}' did not match the response"
+                    ))
+                }),
+            Extractor::JsonPointer(pointer) => {
+                let body: serde_json::Value = serde_json::from_str(response).map_err(|err| {
+                    ClientError::CustomExtract(format!("response is not valid JSON: {err}"))
+                })?;
+                body.pointer(pointer)
+                    .and_then(|value| value.as_str())
+                    .map(|value| value.trim().to_string())
+                    .ok_or_else(|| {
+                        ClientError::CustomExtract(format!(
+                            "no string found at JSON pointer '{pointer}'"
+                        ))
+                    })
+            }
+        }
+    }
+
+    async fn get_ip_from(&self, url: &Option<String>, kind: &str) -> Result<String, ClientError> {
+        let url = url
+            .as_ref()
+            .ok_or_else(|| ClientError::CustomMissingUrl(kind.to_string()))?;
+        self.extract(&get_ip(url).await?)
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceCustom {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        parse_ipv4(&self.get_ip_from(&self.ipv4_url, "IPv4").await?)
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        parse_ipv6(&self.get_ip_from(&self.ipv6_url, "IPv6").await?)
+    }
+    fn name(&self) -> String {
+        "custom".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use httpmock::MockServer;
+
+    use super::IPSourceCustom;
+    use crate::{
+        config::{CustomExtract, CustomIPSource},
+        ip_source::common::IPSource,
+        ClientError,
+    };
+
+    fn source(server: &MockServer, extract: CustomExtract) -> IPSourceCustom {
+        IPSourceCustom::new(&CustomIPSource {
+            ipv4_url: Some(server.url("/v4")),
+            ipv6_url: None,
+            extract,
+        })
+        .expect("Failed to create the IP source")
+    }
+
+    #[tokio::test]
+    async fn text_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/v4");
+            then.status(200).body("93.184.216.34\n");
+        });
+
+        let ipv4 = source(&server, CustomExtract::Text)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn regex_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/v4");
+            then.status(200)
+                .body("<html><body>Current IP Address: 93.184.216.34</body></html>");
+        });
+
+        let ipv4 = source(
+            &server,
+            CustomExtract::Regex(r"Address: ([0-9.]+)".to_string()),
+        )
+        .get_ipv4()
+        .await
+        .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn json_pointer_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/v4");
+            then.status(200)
+                .body(r#"{"client": {"address": "93.184.216.34", "port": 5555}}"#);
+        });
+
+        let ipv4 = source(
+            &server,
+            CustomExtract::JsonPointer("/client/address".to_string()),
+        )
+        .get_ipv4()
+        .await
+        .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn missing_url_test() {
+        let server = MockServer::start();
+        let result = source(&server, CustomExtract::Text).get_ipv6().await;
+        assert!(matches!(result, Err(ClientError::CustomMissingUrl(_))));
+    }
+
+    #[test]
+    fn invalid_regex_test() {
+        let result = IPSourceCustom::new(&CustomIPSource {
+            ipv4_url: None,
+            ipv6_url: None,
+            extract: CustomExtract::Regex("(".to_string()),
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use async_trait::async_trait;
+
+use crate::config::DnsIPSource;
+use crate::dns::{self, txt_strings, TYPE_A, TYPE_AAAA, TYPE_TXT};
+use crate::ClientError;
+
+use super::common::{
+    find_ip, lookup_server, parse_ipv4, parse_ipv6, public_ipv4, public_ipv6, IPSource,
+};
+
+/// Finds out the IP address with a DNS query to a server that answers with
+/// the address the query came from, like OpenDNS or Google.
+pub(crate) struct IPSourceDns {
+    pub(crate) config: DnsIPSource,
+}
+
+impl IPSourceDns {
+    /// Returns the strings from all TXT records of the name.
+    async fn query_txt(&self, server: SocketAddr) -> Result<Vec<String>, ClientError> {
+        let mut strings = Vec::new();
+        for record in dns::query(server, &self.config.name, TYPE_TXT).await? {
+            strings.extend(txt_strings(&record.data)?);
+        }
+        Ok(strings)
+    }
+
+    /// Returns the data of all records of the name with this type.
+    async fn query_address(
+        &self,
+        server: SocketAddr,
+        qtype: u16,
+    ) -> Result<Vec<Vec<u8>>, ClientError> {
+        Ok(dns::query(server, &self.config.name, qtype)
+            .await?
+            .into_iter()
+            .map(|record| record.data)
+            .collect())
+    }
+
+    fn no_answer(&self) -> ClientError {
+        ClientError::DnsNoAnswer(self.config.name.clone())
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceDns {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        // The server answers with the address the query came from, so it has
+        // to be reached over the IP version we want to learn.
+        let server = lookup_server(&self.config.resolver, self.config.port, true).await?;
+        if self.config.txt {
+            // Some servers add more TXT strings with other information, so
+            // find the one that is an address.
+            let strings = self.query_txt(server).await?;
+            if strings.is_empty() {
+                return Err(self.no_answer());
+            }
+            find_ip(strings.iter().map(String::as_str), parse_ipv4)
+        } else {
+            let data = self.query_address(server, TYPE_A).await?;
+            let octets: [u8; 4] = data
+                .first()
+                .and_then(|data| data.as_slice().try_into().ok())
+                .ok_or_else(|| self.no_answer())?;
+            public_ipv4(Ipv4Addr::from(octets))
+        }
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        let server = lookup_server(&self.config.resolver, self.config.port, false).await?;
+        if self.config.txt {
+            let strings = self.query_txt(server).await?;
+            if strings.is_empty() {
+                return Err(self.no_answer());
+            }
+            find_ip(strings.iter().map(String::as_str), parse_ipv6)
+        } else {
+            let data = self.query_address(server, TYPE_AAAA).await?;
+            let octets: [u8; 16] = data
+                .first()
+                .and_then(|data| data.as_slice().try_into().ok())
+                .ok_or_else(|| self.no_answer())?;
+            public_ipv6(Ipv6Addr::from(octets))
+        }
+    }
+    fn name(&self) -> String {
+        format!("DNS {} at {}", self.config.name, self.config.resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::UdpSocket;
+
+    use super::IPSourceDns;
+    use crate::{
+        config::DnsIPSource,
+        dns::{Message, Record, CLASS_IN, TYPE_A, TYPE_TXT},
+        ip_source::common::IPSource,
+        ClientError,
+    };
+
+    /// Starts a DNS server that answers a single query with these records, and
+    /// returns the port it listens on.
+    async fn stub_server(answers: Vec<(u16, Vec<u8>)>) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind stub server");
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let query = Message::decode(&buf[..len]).unwrap();
+            let question = query.questions[0].clone();
+            let response = Message {
+                id: query.id,
+                flags: 0x8180,
+                answers: answers
+                    .into_iter()
+                    .filter(|(rtype, _)| *rtype == question.qtype)
+                    .map(|(rtype, data)| Record {
+                        name: question.name.clone(),
+                        rtype,
+                        class: CLASS_IN,
+                        ttl: 0,
+                        data,
+                    })
+                    .collect(),
+                questions: vec![question],
+                ..Message::default()
+            };
+            socket.send_to(&response.encode(), peer).await.unwrap();
+        });
+        port
+    }
+
+    fn source(port: u16, txt: bool) -> IPSourceDns {
+        IPSourceDns {
+            config: DnsIPSource {
+                name: "myip.example.com".to_string(),
+                resolver: "127.0.0.1".to_string(),
+                port,
+                txt,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn a_record_test() {
+        let port = stub_server(vec![(TYPE_A, vec![93, 184, 216, 34])]).await;
+        let ipv4 = source(port, false)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn txt_record_test() {
+        let mut data = vec![13];
+        data.extend_from_slice(b"93.184.216.34");
+        data.push(13);
+        data.extend_from_slice(b"edns0-client");
+        data.push(b'!');
+        let port = stub_server(vec![(TYPE_TXT, data)]).await;
+        let ipv4 = source(port, true)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn no_answer_test() {
+        let port = stub_server(vec![]).await;
+        let result = source(port, false).get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::DnsNoAnswer(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn opendns_test() {
+        IPSourceDns {
+            config: DnsIPSource::default(),
+        }
+        .get_ipv4()
+        .await
+        .expect("Failed to get the IP address");
+    }
+}
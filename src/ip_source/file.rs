@@ -0,0 +1,86 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use crate::ClientError;
+
+use super::common::{find_ip, parse_ipv4, parse_ipv6, IPSource};
+
+/// Reads the IP address from a file, which something else keeps up to date.
+/// If the file has multiple lines, the first one that is an address of the
+/// requested IP version is used.
+pub(crate) struct IPSourceFile {
+    pub(crate) path: PathBuf,
+}
+
+impl IPSourceFile {
+    async fn read(&self) -> Result<String, ClientError> {
+        tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|err| ClientError::File(self.path.to_string_lossy().to_string(), err))
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceFile {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        find_ip(self.read().await?.lines(), parse_ipv4)
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        find_ip(self.read().await?.lines(), parse_ipv6)
+    }
+    fn name(&self) -> String {
+        format!("file '{}'", self.path.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use tokio::fs;
+
+    use super::IPSourceFile;
+    use crate::{ip_source::common::IPSource, ClientError};
+
+    #[tokio::test]
+    async fn file_test() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("wan-ip");
+        fs::write(&temp, "2606:4700::1111\n93.184.216.34\n")
+            .await
+            .expect("Failed to write test file");
+
+        let source = IPSourceFile { path: temp };
+        assert_eq!(
+            source
+                .get_ipv4()
+                .await
+                .expect("Failed to get the IP address"),
+            Ipv4Addr::new(93, 184, 216, 34)
+        );
+        assert_eq!(
+            source
+                .get_ipv6()
+                .await
+                .expect("Failed to get the IP address"),
+            "2606:4700::1111".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_file_test() {
+        let source = IPSourceFile {
+            path: temp_dir().join("gandi-live-dns-test").join("missing"),
+        };
+        assert!(matches!(
+            source.get_ipv4().await,
+            Err(ClientError::File(_, _))
+        ));
+    }
+}
@@ -0,0 +1,447 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Url;
+use tokio::{net::UdpSocket, time::timeout};
+
+use crate::config::{GatewayIPSource, GatewayProtocol};
+use crate::ClientError;
+
+use super::common::{parse_ipv4, public_ipv4, IPSource};
+
+/// NAT-PMP and PCP both listen on this port, see RFC 6887 section 19.1.
+const NAT_PMP_PORT: u16 = 5351;
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+/// Requests are resent if there's no response, waiting twice as long each time
+/// like RFC 6886 section 3.1 suggests.
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const MAX_ATTEMPTS: u32 = 4;
+/// PCP has no way to only ask for the external address, so we request a
+/// short-lived mapping for the port we send the request from. Nothing listens
+/// on that port once we are done, and the mapping expires shortly after.
+const PCP_MAPPING_LIFETIME: u32 = 30;
+const PCP_OPCODE_MAP: u8 = 1;
+const PROTOCOL_UDP: u8 = 17;
+
+/// The services that can tell us the external address, see the UPnP IGD spec.
+const UPNP_SERVICES: &[&str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+
+/// Asks the router for its external address.
+pub(crate) struct IPSourceGateway {
+    pub(crate) config: GatewayIPSource,
+}
+
+fn gateway_error<E: ToString>(err: E) -> ClientError {
+    ClientError::Gateway(err.to_string())
+}
+
+/// Finds the default gateway in the contents of `/proc/net/route`. Each line
+/// holds the interface, destination, gateway, and some other fields, with the
+/// addresses in hex and in the byte order of the host.
+#[cfg(any(target_os = "linux", test))]
+fn parse_default_gateway(contents: &str) -> Option<Ipv4Addr> {
+    contents.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[1] != "00000000" {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+        if gateway == 0 {
+            return None;
+        }
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Result<Ipv4Addr, ClientError> {
+    let contents = std::fs::read_to_string("/proc/net/route").map_err(gateway_error)?;
+    parse_default_gateway(&contents)
+        .ok_or_else(|| gateway_error("could not find the default gateway"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Result<Ipv4Addr, ClientError> {
+    Err(gateway_error(
+        "can't find the default gateway on this platform, set the gateway address in the config",
+    ))
+}
+
+/// Finds the contents of the first `<tag>...</tag>` in the XML.
+fn xml_element<'x>(xml: &'x str, tag: &str) -> Option<&'x str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim())
+}
+
+/// Sends the request, resending it until a response arrives. The responses are
+/// passed to `parse`, which returns `None` for packets that should be ignored.
+async fn udp_request<T, F>(socket: &UdpSocket, request: &[u8], parse: F) -> Result<T, ClientError>
+where
+    F: Fn(&[u8]) -> Option<Result<T, ClientError>>,
+{
+    let mut buf = [0u8; 1100];
+    let mut wait = INITIAL_TIMEOUT;
+    for _ in 0..MAX_ATTEMPTS {
+        socket.send(request).await.map_err(gateway_error)?;
+        let received = timeout(wait, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if let Some(result) = parse(&buf[..len]) {
+                    return Ok::<_, std::io::Error>(result);
+                }
+            }
+        })
+        .await;
+        match received {
+            Ok(Ok(result)) => return result,
+            Ok(Err(err)) => return Err(gateway_error(err)),
+            // Timed out, try again
+            Err(_) => wait *= 2,
+        }
+    }
+    Err(gateway_error("the gateway did not respond in time"))
+}
+
+impl IPSourceGateway {
+    fn gateway(&self) -> Result<IpAddr, ClientError> {
+        match self.config.address {
+            Some(address) => Ok(address),
+            None => Ok(IpAddr::V4(default_gateway()?)),
+        }
+    }
+
+    async fn connect(&self) -> Result<UdpSocket, ClientError> {
+        let gateway = SocketAddr::new(self.gateway()?, self.config.port.unwrap_or(NAT_PMP_PORT));
+        let bind: SocketAddr = if gateway.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind).await.map_err(gateway_error)?;
+        socket.connect(gateway).await.map_err(gateway_error)?;
+        Ok(socket)
+    }
+
+    /// See RFC 6886 section 3.2.
+    async fn nat_pmp(&self) -> Result<Ipv4Addr, ClientError> {
+        let socket = self.connect().await?;
+        udp_request(&socket, &[0, 0], |response| {
+            if response.len() < 12 || response[0] != 0 || response[1] != 128 {
+                return None;
+            }
+            let result = u16::from_be_bytes([response[2], response[3]]);
+            if result != 0 {
+                return Some(Err(gateway_error(format!(
+                    "NAT-PMP request failed with result code {result}"
+                ))));
+            }
+            Some(Ok(Ipv4Addr::new(
+                response[8],
+                response[9],
+                response[10],
+                response[11],
+            )))
+        })
+        .await
+    }
+
+    /// See RFC 6887 sections 7 and 11.
+    async fn pcp(&self) -> Result<Ipv4Addr, ClientError> {
+        let socket = self.connect().await?;
+        let local = socket.local_addr().map_err(gateway_error)?;
+        let client_ip = match local.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        let nonce: [u8; 12] = rand::random();
+
+        let mut request = Vec::with_capacity(60);
+        request.extend_from_slice(&[2, PCP_OPCODE_MAP, 0, 0]);
+        request.extend_from_slice(&PCP_MAPPING_LIFETIME.to_be_bytes());
+        request.extend_from_slice(&client_ip.octets());
+        request.extend_from_slice(&nonce);
+        request.extend_from_slice(&[PROTOCOL_UDP, 0, 0, 0]);
+        request.extend_from_slice(&local.port().to_be_bytes());
+        // Any external port and address
+        request.extend_from_slice(&0u16.to_be_bytes());
+        request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+        udp_request(&socket, &request, |response| {
+            if response.len() < 60 || response[0] != 2 || response[1] != (0x80 | PCP_OPCODE_MAP) {
+                return None;
+            }
+            if response[24..36] != nonce {
+                return None;
+            }
+            let result = response[3];
+            if result != 0 {
+                return Some(Err(gateway_error(format!(
+                    "PCP request failed with result code {result}"
+                ))));
+            }
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&response[44..60]);
+            Some(
+                Ipv6Addr::from(octets)
+                    .to_ipv4_mapped()
+                    .ok_or_else(|| gateway_error("PCP response did not include an IPv4 address")),
+            )
+        })
+        .await
+    }
+
+    /// Finds the location of the gateway's device description with SSDP.
+    async fn ssdp_discover(&self) -> Result<String, ClientError> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(gateway_error)?;
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDRESS}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n"
+        );
+        socket
+            .send_to(request.as_bytes(), SSDP_ADDRESS)
+            .await
+            .map_err(gateway_error)?;
+
+        let mut buf = [0u8; 2048];
+        timeout(SSDP_TIMEOUT, async {
+            loop {
+                let (len, _) = socket.recv_from(&mut buf).await?;
+                let response = String::from_utf8_lossy(&buf[..len]);
+                let location = response.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("location")
+                        .then(|| value.trim().to_string())
+                });
+                if let Some(location) = location {
+                    return Ok::<_, std::io::Error>(location);
+                }
+            }
+        })
+        .await
+        .map_err(|_| gateway_error("no UPnP gateway responded to discovery"))?
+        .map_err(gateway_error)
+    }
+
+    async fn upnp(&self) -> Result<Ipv4Addr, ClientError> {
+        let location = match &self.config.location {
+            Some(location) => location.clone(),
+            None => self.ssdp_discover().await?,
+        };
+        let location = Url::parse(&location).map_err(gateway_error)?;
+        let client = reqwest::Client::new();
+        let description = client
+            .get(location.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let (service_type, control_url) = description
+            .split("<service>")
+            .skip(1)
+            .find_map(|service| {
+                let service_type = xml_element(service, "serviceType")?;
+                if !UPNP_SERVICES
+                    .iter()
+                    .any(|prefix| service_type.starts_with(prefix))
+                {
+                    return None;
+                }
+                Some((service_type, xml_element(service, "controlURL")?))
+            })
+            .ok_or_else(|| {
+                gateway_error("the UPnP gateway does not have a WAN connection service")
+            })?;
+        let control_url = location.join(control_url).map_err(gateway_error)?;
+
+        let body = format!(
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:GetExternalIPAddress xmlns:u="{service_type}"></u:GetExternalIPAddress></s:Body></s:Envelope>"#
+        );
+        let response = client
+            .post(control_url)
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header(
+                "SOAPAction",
+                format!(r#""{service_type}#GetExternalIPAddress""#),
+            )
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let address = xml_element(&response, "NewExternalIPAddress")
+            .ok_or_else(|| gateway_error("the UPnP gateway did not return an address"))?;
+        parse_ipv4(address)
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceGateway {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        match self.config.protocol {
+            GatewayProtocol::UPnP => self.upnp().await,
+            GatewayProtocol::NatPmp => public_ipv4(self.nat_pmp().await?),
+            GatewayProtocol::Pcp => public_ipv4(self.pcp().await?),
+        }
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        // IPv6 addresses aren't translated by the gateway, so there's nothing
+        // it could tell us.
+        Err(gateway_error(
+            "the gateway can only provide an IPv4 address",
+        ))
+    }
+    fn name(&self) -> String {
+        format!("gateway ({:?})", self.config.protocol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use httpmock::MockServer;
+    use tokio::net::UdpSocket;
+
+    use super::{parse_default_gateway, IPSourceGateway};
+    use crate::{
+        config::{GatewayIPSource, GatewayProtocol},
+        ip_source::common::IPSource,
+    };
+
+    /// Starts a server that responds to a single request, and returns the port
+    /// it listens on.
+    async fn stub_server<F>(respond: F) -> u16
+    where
+        F: FnOnce(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind stub server");
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1100];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let response = respond(&buf[..len]);
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        port
+    }
+
+    fn source(
+        protocol: GatewayProtocol,
+        port: Option<u16>,
+        location: Option<String>,
+    ) -> IPSourceGateway {
+        IPSourceGateway {
+            config: GatewayIPSource {
+                protocol,
+                address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                port,
+                location,
+            },
+        }
+    }
+
+    #[test]
+    fn parse_default_gateway_test() {
+        let route = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0
+";
+        assert_eq!(
+            parse_default_gateway(route),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn nat_pmp_test() {
+        let port = stub_server(|request| {
+            assert_eq!(request, [0, 0]);
+            vec![0, 128, 0, 0, 0, 0, 0, 1, 93, 184, 216, 34]
+        })
+        .await;
+        let ipv4 = source(GatewayProtocol::NatPmp, Some(port), None)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn pcp_test() {
+        let port = stub_server(|request| {
+            assert_eq!(request.len(), 60);
+            let mut response = vec![2, 0x81, 0, 0];
+            // Lifetime, epoch and reserved
+            response.extend_from_slice(&[0; 20]);
+            // Nonce, protocol, reserved, and ports are echoed back
+            response.extend_from_slice(&request[24..44]);
+            response.extend_from_slice(&Ipv4Addr::new(93, 184, 216, 34).to_ipv6_mapped().octets());
+            response
+        })
+        .await;
+        let ipv4 = source(GatewayProtocol::Pcp, Some(port), None)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn upnp_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/rootDesc.xml");
+            then.status(200).body(
+                r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#,
+            );
+        });
+        let control = server.mock(|when, then| {
+            when.method("POST")
+                .path("/ctl/IPConn")
+                .header(
+                    "SOAPAction",
+                    "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\"",
+                );
+            then.status(200).body(
+                r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1"><NewExternalIPAddress>93.184.216.34</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body></s:Envelope>"#,
+            );
+        });
+
+        let ipv4 = source(
+            GatewayProtocol::UPnP,
+            None,
+            Some(server.url("/rootDesc.xml")),
+        )
+        .get_ipv4()
+        .await
+        .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+        control.assert();
+    }
+}
@@ -0,0 +1,149 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use async_trait::async_trait;
+use if_addrs::get_if_addrs;
+
+use crate::ClientError;
+
+use super::common::{is_public_ipv4, is_public_ipv6, IPSource};
+
+/// Reads the addresses assigned to a local network interface, for hosts that
+/// have a public address directly on one of their interfaces.
+pub(crate) struct IPSourceInterface {
+    pub(crate) interface: String,
+}
+
+impl IPSourceInterface {
+    fn addresses(&self) -> Result<Vec<IpAddr>, ClientError> {
+        let interfaces = get_if_addrs()
+            .map_err(|err| ClientError::InterfaceRead(self.interface.clone(), err))?;
+        Ok(interfaces
+            .into_iter()
+            .filter(|iface| iface.name == self.interface)
+            .map(|iface| iface.ip())
+            .collect())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn ipv6_addresses(&self) -> Result<Vec<Ipv6Addr>, ClientError> {
+        // getifaddrs doesn't expose the address flags, so we can't tell
+        // temporary or deprecated addresses apart with it. The kernel lists
+        // them along with their flags here.
+        let contents = std::fs::read_to_string("/proc/net/if_inet6")
+            .map_err(|err| ClientError::InterfaceRead(self.interface.clone(), err))?;
+        Ok(parse_if_inet6(&contents, &self.interface))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn ipv6_addresses(&self) -> Result<Vec<Ipv6Addr>, ClientError> {
+        Ok(self
+            .addresses()?
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V6(ip) => Some(ip),
+                IpAddr::V4(_) => None,
+            })
+            .collect())
+    }
+}
+
+/// Flags from `linux/if_addr.h` for addresses we should not publish.
+#[cfg(any(target_os = "linux", test))]
+const IFA_F_TEMPORARY: u32 = 0x01;
+#[cfg(any(target_os = "linux", test))]
+const IFA_F_DADFAILED: u32 = 0x08;
+#[cfg(any(target_os = "linux", test))]
+const IFA_F_DEPRECATED: u32 = 0x20;
+#[cfg(any(target_os = "linux", test))]
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+/// Parses the contents of `/proc/net/if_inet6`, returning the addresses of the
+/// interface that are usable. Each line looks like:
+///
+/// `20010db8000000000000000000000001 02 40 00 80     eth0`
+///
+/// which are the address, interface index, prefix length, scope, flags and the
+/// interface name.
+#[cfg(any(target_os = "linux", test))]
+fn parse_if_inet6(contents: &str, interface: &str) -> Vec<Ipv6Addr> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 6 || fields[5] != interface {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[4], 16).ok()?;
+            if flags & (IFA_F_TEMPORARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE) != 0
+            {
+                return None;
+            }
+            let address = u128::from_str_radix(fields[0], 16).ok()?;
+            Some(Ipv6Addr::from(address))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl IPSource for IPSourceInterface {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        self.addresses()?
+            .into_iter()
+            .find_map(|ip| match ip {
+                IpAddr::V4(ip) if is_public_ipv4(&ip) => Some(ip),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                ClientError::InterfaceNoAddress(self.interface.clone(), "IPv4".to_string())
+            })
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        self.ipv6_addresses()?
+            .into_iter()
+            .find(is_public_ipv6)
+            .ok_or_else(|| {
+                ClientError::InterfaceNoAddress(self.interface.clone(), "IPv6".to_string())
+            })
+    }
+    fn name(&self) -> String {
+        format!("interface {}", self.interface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::{parse_if_inet6, IPSourceInterface};
+    use crate::{ip_source::common::IPSource, ClientError};
+
+    const IF_INET6: &str = "\
+00000000000000000000000000000001 01 80 10 80       lo
+fe800000000000000000000000000001 02 40 20 80     eth0
+2001067c2e8c00000000000000000001 02 40 00 80     eth0
+2001067c2e8c00001c2d3e4f5a6b7c8d 02 40 00 01     eth0
+2001067c2e8c00000000000000000002 02 40 00 a0     eth0
+2001067c2e8c00000000000000000003 03 40 00 80     wlan0
+";
+
+    #[test]
+    fn parse_if_inet6_test() {
+        let addresses = parse_if_inet6(IF_INET6, "eth0");
+        assert_eq!(
+            addresses,
+            vec![
+                "fe80::1".parse::<Ipv6Addr>().unwrap(),
+                "2001:67c:2e8c::1".parse::<Ipv6Addr>().unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn loopback_has_no_public_ipv4() {
+        let source = IPSourceInterface {
+            interface: "lo".to_string(),
+        };
+        let result = source.get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::InterfaceNoAddress(_, _))));
+    }
+}
@@ -1,55 +1,46 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
 use async_trait::async_trait;
 
 use crate::ClientError;
 
-use super::common::IPSource;
+use super::common::{get_ip, parse_ipv4, parse_ipv6, IPSource};
 
 pub(crate) struct IPSourceIpify;
 
-async fn get_ip(api_url: &str) -> Result<String, ClientError> {
-    let response = reqwest::get(api_url).await?;
-    let text = response.text().await?;
-    Ok(text)
-}
-
 #[async_trait]
 impl IPSource for IPSourceIpify {
-    async fn get_ipv4(&self) -> Result<String, ClientError> {
-        get_ip("https://api.ipify.org").await
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        parse_ipv4(&get_ip("https://api.ipify.org").await?)
     }
-    async fn get_ipv6(&self) -> Result<String, ClientError> {
-        get_ip("https://api6.ipify.org").await
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        parse_ipv6(&get_ip("https://api6.ipify.org").await?)
+    }
+    fn name(&self) -> String {
+        "Ipify".to_string()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use regex::Regex;
-
     use super::IPSource;
     use super::IPSourceIpify;
 
     #[tokio::test]
     #[ignore]
     async fn ipv4_test() {
-        let ipv4 = IPSourceIpify
+        IPSourceIpify
             .get_ipv4()
             .await
             .expect("Failed to get the IP address");
-        assert!(Regex::new(r"^\d+[.]\d+[.]\d+[.]\d+$")
-            .unwrap()
-            .is_match(ipv4.as_str()))
     }
 
     #[tokio::test]
     #[ignore]
     async fn ipv6_test() {
-        let ipv6 = IPSourceIpify
+        IPSourceIpify
             .get_ipv6()
             .await
             .expect("Failed to get the IP address");
-        assert!(Regex::new(r"^([0-9a-fA-F]*:){7}[0-9a-fA-F]*$")
-            .unwrap()
-            .is_match(ipv6.as_str()))
     }
 }
@@ -1,4 +1,12 @@
+pub(crate) mod command;
 pub(crate) mod common;
+pub(crate) mod custom;
+pub(crate) mod dns;
+pub(crate) mod file;
+pub(crate) mod gateway;
 pub(crate) mod icanhazip;
+pub(crate) mod interface;
 pub(crate) mod ipify;
+pub(crate) mod multi;
 pub(crate) mod seeip;
+pub(crate) mod stun;
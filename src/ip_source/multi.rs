@@ -0,0 +1,218 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+
+use crate::ClientError;
+
+use super::common::IPSource;
+
+type IPFuture<'s, T> = Pin<Box<dyn Future<Output = Result<T, ClientError>> + Send + 's>>;
+
+pub(crate) enum Strategy {
+    /// Use the first source that succeeds, in order.
+    Fallback,
+    /// At least this many sources have to return the same address.
+    Quorum(usize),
+}
+
+/// Combines multiple IP sources, so that one source being down or returning
+/// bad answers doesn't break the updates.
+pub(crate) struct IPSourceMulti {
+    pub(crate) sources: Vec<Box<dyn IPSource>>,
+    pub(crate) strategy: Strategy,
+}
+
+impl IPSourceMulti {
+    /// Returns the address, and the names of the sources that agreed on it.
+    async fn get_ip<'s, T, F>(&'s self, get: F) -> Result<(T, String), ClientError>
+    where
+        T: PartialEq + Display,
+        F: Fn(&'s dyn IPSource) -> IPFuture<'s, T>,
+    {
+        match self.strategy {
+            Strategy::Fallback => {
+                let mut errors: Vec<String> = Vec::new();
+                for source in &self.sources {
+                    match get(source.as_ref()).await {
+                        Ok(ip) => return Ok((ip, source.name())),
+                        Err(err) => errors.push(err.to_string()),
+                    }
+                }
+                Err(ClientError::AllSourcesFailed(errors.join("; ")))
+            }
+            Strategy::Quorum(quorum) => {
+                let results =
+                    join_all(self.sources.iter().map(|source| get(source.as_ref()))).await;
+                // Collect which sources gave each answer
+                let mut answers: Vec<(T, Vec<String>)> = Vec::new();
+                let mut errors: Vec<String> = Vec::new();
+                for (result, source) in results.into_iter().zip(&self.sources) {
+                    match result {
+                        Ok(ip) => match answers.iter_mut().find(|(answer, _)| *answer == ip) {
+                            Some((_, names)) => names.push(source.name()),
+                            None => answers.push((ip, vec![source.name()])),
+                        },
+                        Err(err) => errors.push(err.to_string()),
+                    }
+                }
+                // A quorum of half the sources or less can be reached by two
+                // answers at once, then there is no telling which one is right.
+                let reached: Vec<usize> = answers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, names))| names.len() >= quorum)
+                    .map(|(index, _)| index)
+                    .collect();
+                match reached[..] {
+                    [index] => {
+                        let (ip, names) = answers.swap_remove(index);
+                        Ok((ip, names.join(", ")))
+                    }
+                    _ => {
+                        let summary = answers
+                            .iter()
+                            .map(|(answer, names)| format!("{answer} ({})", names.len()))
+                            .chain(errors)
+                            .collect::<Vec<String>>()
+                            .join("; ");
+                        Err(ClientError::NoQuorum(quorum, summary))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceMulti {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        Ok(self.get_ipv4_from().await?.0)
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        Ok(self.get_ipv6_from().await?.0)
+    }
+    fn name(&self) -> String {
+        self.sources
+            .iter()
+            .map(|source| source.name())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+    async fn get_ipv4_from(&self) -> Result<(Ipv4Addr, String), ClientError> {
+        self.get_ip(|source| source.get_ipv4()).await
+    }
+    async fn get_ipv6_from(&self) -> Result<(Ipv6Addr, String), ClientError> {
+        self.get_ip(|source| source.get_ipv6()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use async_trait::async_trait;
+
+    use super::{IPSourceMulti, Strategy};
+    use crate::{ip_source::common::IPSource, ClientError};
+
+    struct IPSourceFixed(Option<&'static str>);
+
+    #[async_trait]
+    impl IPSource for IPSourceFixed {
+        async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+            self.0
+                .map(|ip| ip.parse().unwrap())
+                .ok_or_else(|| ClientError::CustomExtract("no address".to_string()))
+        }
+        async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+            Ok(self.get_ipv4().await?.to_ipv6_mapped())
+        }
+        fn name(&self) -> String {
+            self.0.unwrap_or("failing").to_string()
+        }
+    }
+
+    fn multi(answers: &[Option<&'static str>], strategy: Strategy) -> IPSourceMulti {
+        IPSourceMulti {
+            sources: answers
+                .iter()
+                .map(|answer| Box::new(IPSourceFixed(*answer)) as Box<dyn IPSource>)
+                .collect(),
+            strategy,
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_skips_failures() {
+        let source = multi(
+            &[None, Some("203.0.113.1"), Some("203.0.113.2")],
+            Strategy::Fallback,
+        );
+        assert_eq!(
+            source.get_ipv4().await.unwrap(),
+            Ipv4Addr::new(203, 0, 113, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_all_failed() {
+        let source = multi(&[None, None], Strategy::Fallback);
+        assert!(matches!(
+            source.get_ipv4().await,
+            Err(ClientError::AllSourcesFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn quorum_reached() {
+        let source = multi(
+            &[
+                Some("203.0.113.1"),
+                None,
+                Some("203.0.113.2"),
+                Some("203.0.113.2"),
+            ],
+            Strategy::Quorum(2),
+        );
+        assert_eq!(
+            source.get_ipv4_from().await.unwrap(),
+            (
+                Ipv4Addr::new(203, 0, 113, 2),
+                "203.0.113.2, 203.0.113.2".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn quorum_reached_twice() {
+        let source = multi(
+            &[
+                Some("203.0.113.1"),
+                Some("203.0.113.1"),
+                Some("203.0.113.2"),
+                Some("203.0.113.2"),
+            ],
+            Strategy::Quorum(2),
+        );
+        assert!(matches!(
+            source.get_ipv4().await,
+            Err(ClientError::NoQuorum(2, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn quorum_not_reached() {
+        let source = multi(
+            &[Some("203.0.113.1"), None, Some("203.0.113.2")],
+            Strategy::Quorum(2),
+        );
+        assert!(matches!(
+            source.get_ipv4().await,
+            Err(ClientError::NoQuorum(2, _))
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::{net::UdpSocket, time::timeout};
+
+use crate::config::StunIPSource;
+use crate::ClientError;
+
+use super::common::{lookup_server, public_ipv4, public_ipv6, IPSource};
+
+/// See RFC 5389, section 6.
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+/// Some older servers use this type from a draft of the RFC.
+const ATTR_XOR_MAPPED_ADDRESS_OLD: u16 = 0x8020;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// The request is resent if there's no response, waiting twice as long each
+/// time, like RFC 5389 section 7.2.1 suggests.
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Finds out the IP address by asking a STUN server which address our request
+/// came from.
+pub(crate) struct IPSourceStun {
+    pub(crate) config: StunIPSource,
+}
+
+fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // No attributes
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction_id);
+    request
+}
+
+/// Reads the mapped address out of a binding response. Returns `None` if the
+/// message is not a response to our request.
+fn parse_response(
+    response: &[u8],
+    transaction_id: &[u8; 12],
+) -> Option<Result<IpAddr, ClientError>> {
+    if response.len() < 20 || &response[8..20] != transaction_id {
+        return None;
+    }
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    if message_type == BINDING_ERROR {
+        return Some(Err(ClientError::Stun(
+            "server responded with an error".to_string(),
+        )));
+    }
+    if message_type != BINDING_SUCCESS {
+        return None;
+    }
+
+    let mut mapped: Option<IpAddr> = None;
+    let mut offset = 20;
+    while offset + 4 <= response.len() {
+        let attr_type = u16::from_be_bytes([response[offset], response[offset + 1]]);
+        let attr_len = u16::from_be_bytes([response[offset + 2], response[offset + 3]]) as usize;
+        let value = match response.get(offset + 4..offset + 4 + attr_len) {
+            Some(value) => value,
+            None => break,
+        };
+        // Attributes are padded to 4 bytes
+        offset += 4 + attr_len.div_ceil(4) * 4;
+
+        let xor = match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS | ATTR_XOR_MAPPED_ADDRESS_OLD => true,
+            ATTR_MAPPED_ADDRESS => false,
+            _ => continue,
+        };
+        let address = match (value.get(1), value.len()) {
+            (Some(&FAMILY_IPV4), 8) => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(&value[4..8]);
+                if xor {
+                    for (octet, key) in octets.iter_mut().zip(MAGIC_COOKIE.to_be_bytes()) {
+                        *octet ^= key;
+                    }
+                }
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            (Some(&FAMILY_IPV6), 20) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&value[4..20]);
+                if xor {
+                    let key = MAGIC_COOKIE
+                        .to_be_bytes()
+                        .into_iter()
+                        .chain(*transaction_id);
+                    for (octet, key) in octets.iter_mut().zip(key) {
+                        *octet ^= key;
+                    }
+                }
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        // Prefer the XOR'ed address, some NATs rewrite addresses they find in
+        // packets which would break the plain one.
+        if xor || mapped.is_none() {
+            mapped = Some(address);
+        }
+        if xor {
+            break;
+        }
+    }
+    Some(mapped.ok_or_else(|| {
+        ClientError::Stun("response did not include the mapped address".to_string())
+    }))
+}
+
+impl IPSourceStun {
+    async fn get_ip(&self, ipv4: bool) -> Result<IpAddr, ClientError> {
+        let server: SocketAddr = lookup_server(&self.config.server, self.config.port, ipv4).await?;
+        let bind: SocketAddr = if ipv4 {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|err| ClientError::Stun(err.to_string()))?;
+        socket
+            .connect(server)
+            .await
+            .map_err(|err| ClientError::Stun(err.to_string()))?;
+
+        let transaction_id: [u8; 12] = rand::random();
+        let request = binding_request(&transaction_id);
+        let mut buf = [0u8; 512];
+        let mut wait = INITIAL_TIMEOUT;
+        for _ in 0..MAX_ATTEMPTS {
+            socket
+                .send(&request)
+                .await
+                .map_err(|err| ClientError::Stun(err.to_string()))?;
+            let received = timeout(wait, async {
+                loop {
+                    let len = socket.recv(&mut buf).await?;
+                    if let Some(result) = parse_response(&buf[..len], &transaction_id) {
+                        return Ok::<_, std::io::Error>(result);
+                    }
+                }
+            })
+            .await;
+            match received {
+                Ok(Ok(result)) => return result,
+                Ok(Err(err)) => return Err(ClientError::Stun(err.to_string())),
+                // Timed out, try again
+                Err(_) => wait *= 2,
+            }
+        }
+        Err(ClientError::Stun(
+            "server did not respond in time".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl IPSource for IPSourceStun {
+    async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+        match self.get_ip(true).await? {
+            IpAddr::V4(ip) => public_ipv4(ip),
+            ip => Err(ClientError::WrongIpFamily("IPv4".to_string(), ip)),
+        }
+    }
+    async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+        match self.get_ip(false).await? {
+            IpAddr::V6(ip) => public_ipv6(ip),
+            ip => Err(ClientError::WrongIpFamily("IPv6".to_string(), ip)),
+        }
+    }
+    fn name(&self) -> String {
+        format!("STUN {}", self.config.server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use tokio::net::UdpSocket;
+
+    use super::{
+        IPSourceStun, ATTR_XOR_MAPPED_ADDRESS, BINDING_ERROR, BINDING_SUCCESS, FAMILY_IPV4,
+        FAMILY_IPV6, MAGIC_COOKIE,
+    };
+    use crate::{config::StunIPSource, ip_source::common::IPSource, ClientError};
+
+    /// Starts a STUN server that answers a single binding request as if it came
+    /// from `mapped`, and returns the port it listens on. Listens on the
+    /// loopback address of the same family as `mapped`.
+    async fn stub_server(mapped: SocketAddr, message_type: u16) -> u16 {
+        let bind = match mapped {
+            SocketAddr::V4(_) => "127.0.0.1:0",
+            SocketAddr::V6(_) => "[::1]:0",
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .expect("Failed to bind stub server");
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 20);
+
+            let family = match mapped {
+                SocketAddr::V4(_) => FAMILY_IPV4,
+                SocketAddr::V6(_) => FAMILY_IPV6,
+            };
+            let mut attribute = vec![0, family];
+            attribute
+                .extend_from_slice(&(mapped.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+            match mapped.ip() {
+                IpAddr::V4(ip) => {
+                    attribute.extend_from_slice(&(u32::from(ip) ^ MAGIC_COOKIE).to_be_bytes())
+                }
+                IpAddr::V6(ip) => {
+                    // XOR'ed with the cookie followed by the transaction ID
+                    let key = buf[4..20].iter();
+                    attribute.extend(ip.octets().iter().zip(key).map(|(octet, key)| octet ^ key))
+                }
+            }
+
+            let mut response = Vec::new();
+            response.extend_from_slice(&message_type.to_be_bytes());
+            response.extend_from_slice(&(4 + attribute.len() as u16).to_be_bytes());
+            // Same cookie and transaction ID as the request
+            response.extend_from_slice(&buf[4..20]);
+            response.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+            response.extend_from_slice(&(attribute.len() as u16).to_be_bytes());
+            response.extend_from_slice(&attribute);
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        port
+    }
+
+    fn source(server: &str, port: u16) -> IPSourceStun {
+        IPSourceStun {
+            config: StunIPSource {
+                server: server.to_string(),
+                port,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn xor_mapped_address_test() {
+        let port = stub_server("93.184.216.34:5555".parse().unwrap(), BINDING_SUCCESS).await;
+        let ipv4 = source("127.0.0.1", port)
+            .get_ipv4()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv4, Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[tokio::test]
+    async fn xor_mapped_address_ipv6_test() {
+        let port = stub_server("[2606:4700::1111]:5555".parse().unwrap(), BINDING_SUCCESS).await;
+        let ipv6 = source("::1", port)
+            .get_ipv6()
+            .await
+            .expect("Failed to get the IP address");
+        assert_eq!(ipv6, "2606:4700::1111".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn error_response_test() {
+        let port = stub_server("93.184.216.34:5555".parse().unwrap(), BINDING_ERROR).await;
+        let result = source("127.0.0.1", port).get_ipv4().await;
+        assert!(matches!(result, Err(ClientError::Stun(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn public_server_test() {
+        IPSourceStun {
+            config: StunIPSource::default(),
+        }
+        .get_ipv4()
+        .await
+        .expect("Failed to get the IP address");
+    }
+}
@@ -1,29 +1,41 @@
-use crate::config::Config;
-use crate::gandi::GandiAPI;
+use crate::config::{Config, Entry};
 use crate::ip_source::{common::IPSource, ipify::IPSourceIpify};
+use crate::provider::{Providers, Rrset};
 use clap::Parser;
-use config::{ConfigError, IPSourceName};
+use config::{ConfigError, IPSourceMode, IPSourceName};
+use ip_source::command::{IPSourceCommand, COMMAND_TIMEOUT};
+use ip_source::custom::IPSourceCustom;
+use ip_source::dns::IPSourceDns;
+use ip_source::file::IPSourceFile;
+use ip_source::gateway::IPSourceGateway;
 use ip_source::icanhazip::IPSourceIcanhazip;
+use ip_source::interface::IPSourceInterface;
+use ip_source::multi::{IPSourceMulti, Strategy};
 use ip_source::seeip::IPSourceSeeIP;
-use opts::Opts;
+use ip_source::stun::IPSourceStun;
+use opts::{Command, Opts, OutputFormat};
+use report::{IpReport, RecordReport, RecordStatus, RunReport};
 use reqwest::header::InvalidHeaderValue;
-use reqwest::{header, Client, ClientBuilder, StatusCode};
-use serde::{Deserialize, Serialize};
-use std::{num::NonZeroU32, sync::Arc, time::Duration};
-use tokio::join;
-use tokio::{self, task::JoinHandle, time::sleep};
+use reqwest::StatusCode;
+use state::State;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::{self, join, select, task::JoinHandle, time::sleep};
+mod acme;
 mod config;
-mod gandi;
+mod dns;
 mod ip_source;
 mod opts;
+mod provider;
+mod records;
+mod report;
+mod retry;
+mod serve;
+mod state;
+mod watch;
 use die_exit::*;
 use thiserror::Error;
 
-/// 30 requests per minute, see https://api.gandi.net/docs/reference/
-const GANDI_RATE_LIMIT: u32 = 30;
-/// If we hit the rate limit, wait up to this many seconds before next attempt
-const GANDI_DELAY_JITTER: u64 = 20;
-
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Error occured while reading config: {0}")]
@@ -42,6 +54,58 @@ pub enum ClientError {
     Ipv4missing(String, String),
     #[error("Entry '{0}' includes type AAAA which requires an IPv6 adress but no IPv6 adress could be determined because: {1}")]
     Ipv6missing(String, String),
+    #[error("Error while reading addresses of network interface '{0}': {1}")]
+    InterfaceRead(String, std::io::Error),
+    #[error("Network interface '{0}' has no usable public {1} address")]
+    InterfaceNoAddress(String, String),
+    #[error("The custom IP source has no URL configured for {0}")]
+    CustomMissingUrl(String),
+    #[error("Failed to extract the IP address from the custom IP source response: {0}")]
+    CustomExtract(String),
+    #[error("All IP sources failed: {0}")]
+    AllSourcesFailed(String),
+    #[error("No single address was returned by at least {0} IP sources, got: {1}")]
+    NoQuorum(usize, String),
+    #[error("The IP source returned '{0}', which is not an IP address")]
+    InvalidIp(String),
+    #[error("Expected an {0} address from the IP source, but got {1}")]
+    WrongIpFamily(String, IpAddr),
+    #[error("The IP source returned {0}, which is not a public IP address")]
+    NonPublicIp(IpAddr),
+    #[error("Error while querying DNS: {0}")]
+    Dns(#[from] dns::DnsError),
+    #[error("Could not find an {1} address for the server '{0}': {2}")]
+    ServerLookup(String, String, String),
+    #[error("The DNS server did not respond with an address for '{0}'")]
+    DnsNoAnswer(String),
+    #[error("Error while querying the STUN server: {0}")]
+    Stun(String),
+    #[error("Error while asking the gateway for the external address: {0}")]
+    Gateway(String),
+    #[error("Command '{0}' failed: {1}")]
+    Command(String, String),
+    #[error("Failed to read the IP address from file '{0}': {1}")]
+    File(String, std::io::Error),
+    #[error("Failed to watch for address changes: {0}")]
+    Watch(std::io::Error),
+    #[error("Error while updating the dyndns2 service: {0}")]
+    Dyndns2(#[from] Dyndns2Error),
+    #[error("Error in the dyndns2 server: {0}")]
+    Serve(#[from] hyper::Error),
+    #[error("Cloudflare API returned {0}: {1}")]
+    Cloudflare(StatusCode, String),
+    #[error("PowerDNS API returned {0}: {1}")]
+    PowerDns(StatusCode, String),
+    #[error("The zone '{0}' was not found in the account of the provider")]
+    ZoneNotFound(String),
+    #[error("The {0} provider does not support {1} records")]
+    UnsupportedType(String, String),
+    #[error("The {0} value '{1}' can't be sent to the DNS server: {2}")]
+    Rdata(String, String, String),
+    #[error("No {0} was given, and {1} is not set")]
+    ChallengeMissing(String, String),
+    #[error("'{0}' is not in the domain of the config or of any entry")]
+    ChallengeNoZone(String),
 }
 
 #[derive(Error, Debug)]
@@ -50,229 +114,412 @@ pub enum ApiError {
     Forbidden { message: String },
     #[error("API returned 403 - Unauthorized. Provided API key is possibly incorrect")]
     Unauthorized(),
+    #[error("API returned 401 - Unauthorized. The personal access token is possibly incorrect or has expired")]
+    TokenRejected(),
+    #[error("API returned 403 - Forbidden. The personal access token possibly lacks the permission to manage the technical configuration (LiveDNS) of this domain. Message: {message:?}")]
+    TokenMissingScope { message: String },
+    #[error("API returned 429 - Too Many Requests. Gave up retrying")]
+    RateLimited(),
     #[error("API returned {0} - {0}")]
     Unknown(StatusCode, String),
 }
 
-fn api_client(api_key: &str) -> Result<Client, ClientError> {
-    let client_builder = ClientBuilder::new();
-
-    let key = format!("Apikey {api_key}");
-    let mut auth_value = header::HeaderValue::from_str(&key)?;
-    let mut headers = header::HeaderMap::new();
-    auth_value.set_sensitive(true);
-    headers.insert(header::AUTHORIZATION, auth_value);
-    let accept_value = header::HeaderValue::from_static("application/json");
-    headers.insert(header::ACCEPT, accept_value);
-    let client = client_builder.default_headers(headers).build()?;
-    Ok(client)
-}
-
-#[derive(Serialize)]
-pub struct APIPayload {
-    pub rrset_values: Vec<String>,
-    pub rrset_ttl: u32,
+/// The errors a dyndns2 service answers with, see
+/// https://help.dyn.com/remote-access-api/return-codes/
+#[derive(Error, Debug, Clone)]
+pub enum Dyndns2Error {
+    #[error("The service answered badauth. The username or password is wrong")]
+    BadAuth(),
+    #[error("The service answered abuse. The host is blocked for sending too many updates")]
+    Abuse(),
+    #[error("The service answered nohost. The host doesn't exist in this account")]
+    NoHost(),
+    #[error("The service answered notfqdn. The host is not a fully qualified domain name")]
+    NotFqdn(),
+    #[error("The service answered badagent. It rejected the request or the client")]
+    BadAgent(),
+    #[error("The service answered !donator. The update needs a paid account")]
+    NotDonator(),
+    #[error("The service answered {0}. It has a problem, try again later")]
+    ServerError(String),
+    #[error("The service answered with '{0}', which is not a dyndns2 response")]
+    Unknown(String),
 }
 
 #[derive(Debug)]
 struct ResponseFeedback {
     entry_name: String,
     entry_type: String,
-    response: Result<String, ApiError>,
-}
-
-#[derive(Deserialize)]
-// Allowing dead code because this is the API response we get from Gandi.
-// We don't necessarily need all the fields, but we get them anyway.
-#[allow(dead_code)]
-struct ApiResponse {
-    message: String,
+    /// Whether the records had to be changed, or were already correct.
+    changed: bool,
+    response: Result<String, ClientError>,
+    /// The cause the provider gave along with its message, if any.
     cause: Option<String>,
-    code: Option<i32>,
-    object: Option<String>,
 }
 
+/// Returns whether any records were changed, or would be changed in a dry run.
 async fn run(
-    base_url: &str,
     ip_source: &Box<dyn IPSource>,
     conf: &Config,
     opts: &Opts,
-) -> Result<(), ClientError> {
-    let mut last_ipv4: Option<String> = None;
-    let mut last_ipv6: Option<String> = None;
+) -> Result<bool, ClientError> {
+    // Progress messages are only printed for people, JSON output gets a single
+    // document at the end of each run instead.
+    let text = opts.output == OutputFormat::Text;
+    let providers = Providers::new(conf)?;
+    // Start listening before the first run, so changes during it aren't missed
+    let mut watcher = match opts.watch {
+        true => Some(watch::Watcher::new()?),
+        false => None,
+    };
+    let mut any_changed = false;
+    let state_path = Config::state_file(conf);
+    let mut state = match &state_path {
+        Some(path) => State::load(path).unwrap_or_else(|err| {
+            eprintln!("Ignoring the state file {}: {err}", path.to_string_lossy());
+            State::default()
+        }),
+        None => State::default(),
+    };
 
     loop {
-        println!("Finding out the IP address...");
-        let (ipv4_result, ipv6_result) = join!(ip_source.get_ipv4(), ip_source.get_ipv6());
-        let ipv4 = ipv4_result.as_ref();
-        let ipv6 = ipv6_result.as_ref();
-        println!("Found these:");
-        match ipv4 {
-            Ok(ip) => println!("\tIPv4: {ip}"),
-            Err(err) => eprintln!("\tIPv4 failed: {err}"),
+        if text {
+            println!("Finding out the IP address...");
         }
-        match ipv6 {
-            Ok(ip) => println!("\tIPv6: {ip}"),
-            Err(err) => eprintln!("\tIPv6 failed: {err}"),
+        let (ipv4_result, ipv6_result) =
+            join!(ip_source.get_ipv4_from(), ip_source.get_ipv6_from());
+        let ipv4 = ipv4_result.as_ref().map(|(ip, _)| ip);
+        let ipv6 = ipv6_result.as_ref().map(|(ip, _)| ip);
+        if text {
+            println!("Found these:");
+            match ipv4 {
+                Ok(ip) => println!("\tIPv4: {ip}"),
+                Err(err) => eprintln!("\tIPv4 failed: {err}"),
+            }
+            match ipv6 {
+                Ok(ip) => println!("\tIPv6: {ip}"),
+                Err(err) => eprintln!("\tIPv6 failed: {err}"),
+            }
         }
 
-        let ipv4_same = last_ipv4
-            .as_ref()
-            .map(|p| ipv4.map(|q| p == q).unwrap_or(false))
-            .unwrap_or(false);
-        let ipv6_same = last_ipv6
-            .as_ref()
-            .map(|p| ipv6.map(|q| p == q).unwrap_or(false))
-            .unwrap_or(false);
-
-        if !ipv4_same || !ipv6_same || conf.always_update {
-            let client = api_client(&conf.api_key)?;
-            let mut tasks: Vec<JoinHandle<Result<ResponseFeedback, ClientError>>> = Vec::new();
-            println!("Attempting to update DNS entries now");
-
-            let governor = Arc::new(governor::RateLimiter::direct(governor::Quota::per_minute(
-                NonZeroU32::new(GANDI_RATE_LIMIT).die("Governor rate is 0"),
-            )));
-            let retry_jitter =
-                governor::Jitter::new(Duration::ZERO, Duration::from_secs(GANDI_DELAY_JITTER));
-
-            for entry in &conf.entry {
-                for entry_type in Config::types(entry) {
-                    let fqdn = Config::fqdn(entry, conf).to_string();
-                    let url = GandiAPI {
-                        fqdn: &fqdn,
-                        rrset_name: &entry.name,
-                        rrset_type: entry_type,
-                        base_url,
-                    }
-                    .url();
-                    let ip = match entry_type {
+        // Figure out which records changed since we last published them
+        let mut first_error: Option<ClientError> = None;
+        let mut pending: Vec<(&Entry, &str, String, Rrset)> = Vec::new();
+        let mut records: Vec<RecordReport> = Vec::new();
+        for entry in &conf.entry {
+            for entry_type in Config::types(entry) {
+                let fqdn = Config::fqdn(entry, conf).to_string();
+                let values = match Config::values(entry) {
+                    Some(values) => Ok(values),
+                    None => match entry_type {
                         "A" => match ipv4 {
-                            Ok(ref value) => Ok(value),
+                            Ok(value) => Ok(IpAddr::V4(*value)),
                             Err(ref err) => Err(ClientError::Ipv4missing(
                                 entry.name.clone(),
                                 err.to_string(),
                             )),
                         },
                         "AAAA" => match ipv6 {
-                            Ok(ref value) => Ok(value),
+                            Ok(value) => Ok(IpAddr::V6(Config::ipv6(entry, conf, *value))),
                             Err(ref err) => Err(ClientError::Ipv6missing(
                                 entry.name.clone(),
                                 err.to_string(),
                             )),
                         },
                         &_ => Err(ClientError::BadEntry(entry_type.to_string())),
-                    }?;
-                    let payload = APIPayload {
-                        rrset_values: vec![ip.to_string()],
-                        rrset_ttl: Config::ttl(entry, conf),
-                    };
-                    let req = client.put(url).json(&payload);
-                    let task_governor = governor.clone();
-                    let entry_type = entry_type.to_string();
-                    let entry_name = entry.name.to_string();
-
-                    let task: JoinHandle<Result<ResponseFeedback, ClientError>> =
-                        tokio::task::spawn(async move {
-                            task_governor.until_ready_with_jitter(retry_jitter).await;
-                            println!("Updating {} record for {}", entry_type, &fqdn);
+                    }
+                    .map(|ip| vec![ip.to_string()]),
+                };
+                // The other records can still be updated, and the loop keeps
+                // going so this is tried again on the next repeat
+                let values = match values {
+                    Ok(values) => values,
+                    Err(err) => {
+                        if text {
+                            eprintln!("Record '{}' ({}): {err}", entry.name, entry_type);
+                        }
+                        records.push(RecordReport {
+                            entry_name: entry.name.clone(),
+                            entry_type: entry_type.to_string(),
+                            fqdn,
+                            status: RecordStatus::Failed,
+                            message: Some(err.to_string()),
+                            cause: None,
+                        });
+                        first_error.get_or_insert(err);
+                        continue;
+                    }
+                };
+                let rrset = Rrset {
+                    values,
+                    ttl: Config::ttl(entry, conf),
+                };
+                let published = state
+                    .get(&fqdn, &entry.name, entry_type)
+                    .is_some_and(|last| last.values == rrset.values && last.ttl == rrset.ttl);
+                // A dry run always compares with the provider, because the state file
+                // only knows what we published and not what's there now
+                if !published || conf.always_update || opts.dry_run {
+                    pending.push((entry, entry_type, fqdn, rrset));
+                } else {
+                    records.push(RecordReport {
+                        entry_name: entry.name.clone(),
+                        entry_type: entry_type.to_string(),
+                        fqdn,
+                        status: RecordStatus::Skipped,
+                        message: None,
+                        cause: None,
+                    });
+                }
+            }
+        }
 
-                            let resp = req.send().await?;
+        if !pending.is_empty() {
+            let mut tasks: Vec<JoinHandle<Result<ResponseFeedback, ClientError>>> = Vec::new();
+            if text && opts.dry_run {
+                println!("Checking DNS entries now, nothing will be changed");
+            } else if text {
+                println!("Attempting to update DNS entries now");
+            }
 
-                            let response_feedback = match resp.status() {
-                                StatusCode::CREATED => {
-                                    let body: ApiResponse = resp.json().await?;
-                                    ResponseFeedback {
-                                        entry_name,
-                                        entry_type,
-                                        response: Ok(body.message),
-                                    }
-                                }
-                                StatusCode::UNAUTHORIZED => ResponseFeedback {
-                                    entry_name,
-                                    entry_type,
-                                    response: Err(ApiError::Unauthorized()),
-                                },
-                                StatusCode::FORBIDDEN => {
-                                    let body: ApiResponse = resp.json().await?;
-                                    ResponseFeedback {
-                                        entry_name: entry_name.clone(),
-                                        entry_type,
-                                        response: Err(ApiError::Forbidden {
-                                            message: body.message,
-                                        }),
-                                    }
-                                }
-                                _ => {
-                                    let status = resp.status();
-                                    let body: ApiResponse = resp.json().await?;
-                                    ResponseFeedback {
+            for (entry, entry_type, fqdn, rrset) in &pending {
+                let provider = providers.get(Config::provider(entry));
+                let rrset = rrset.clone();
+                let fqdn = fqdn.clone();
+                let dry_run = opts.dry_run;
+                let entry_type = entry_type.to_string();
+                let entry_name = entry.name.to_string();
+
+                let task: JoinHandle<Result<ResponseFeedback, ClientError>> =
+                    tokio::task::spawn(async move {
+                        // Check the current records first, so we don't
+                        // spend requests on records that are already right
+                        let current =
+                            match provider.get_rrset(&fqdn, &entry_name, &entry_type).await {
+                                Ok(current) => current,
+                                Err(err) => {
+                                    return Ok(ResponseFeedback {
                                         entry_name,
                                         entry_type,
-                                        response: Err(ApiError::Unknown(status, body.message)),
-                                    }
+                                        changed: false,
+                                        response: Err(err),
+                                        cause: None,
+                                    })
                                 }
                             };
-                            Ok(response_feedback)
-                        });
-                    tasks.push(task);
-                }
+                        if current
+                            .as_ref()
+                            .is_some_and(|current| current.same_as(&rrset))
+                        {
+                            return Ok(ResponseFeedback {
+                                entry_name,
+                                entry_type,
+                                changed: false,
+                                response: Ok("Record is already up to date".to_string()),
+                                cause: None,
+                            });
+                        }
+                        if dry_run {
+                            return Ok(ResponseFeedback {
+                                response: Ok(rrset.describe_change(current.as_ref())),
+                                entry_name,
+                                entry_type,
+                                changed: true,
+                                cause: None,
+                            });
+                        }
+
+                        if text {
+                            println!("Updating {} record for {}", entry_type, &fqdn);
+                        }
+                        let response = provider
+                            .set_rrset(&fqdn, &entry_name, &entry_type, &rrset)
+                            .await;
+                        Ok(ResponseFeedback {
+                            entry_name,
+                            entry_type,
+                            changed: true,
+                            cause: response
+                                .as_ref()
+                                .ok()
+                                .and_then(|published| published.cause.clone()),
+                            response: response.map(|published| published.message),
+                        })
+                    });
+                tasks.push(task);
             }
 
             let results = futures::future::try_join_all(tasks).await?;
             // Only count successfull requests
-            println!(
-                "Updates done for {} entries",
-                results
-                    .iter()
-                    .filter_map(|item| item.as_ref().ok())
-                    .filter(|item| item.response.is_ok())
-                    .count()
-            );
-            for item in &results {
-                match item {
-                    Ok(value) => println!(
-                        "{}",
-                        match &value.response {
-                            Ok(val) => format!(
-                                "Record '{}' ({}): {}",
-                                value.entry_name, value.entry_type, val
-                            ),
-                            Err(err) => format!(
-                                "Record '{}' ({}): {}",
-                                value.entry_name, value.entry_type, err
-                            ),
-                        }
+            let successful = results
+                .iter()
+                .filter_map(|item| item.as_ref().ok())
+                .filter(|item| item.response.is_ok());
+            let changed = successful.clone().filter(|item| item.changed).count();
+            any_changed |= changed > 0;
+            if text && opts.dry_run {
+                println!(
+                    "{} entries would be updated, {} are already up to date",
+                    changed,
+                    successful.filter(|item| !item.changed).count()
+                );
+            } else if text {
+                println!(
+                    "Updates done for {} entries, {} were already up to date",
+                    changed,
+                    successful.filter(|item| !item.changed).count()
+                );
+            }
+            if text {
+                for item in &results {
+                    match item {
+                        Ok(value) => println!(
+                            "{}",
+                            match &value.response {
+                                Ok(val) => format!(
+                                    "Record '{}' ({}): {}",
+                                    value.entry_name, value.entry_type, val
+                                ),
+                                Err(err) => format!(
+                                    "Record '{}' ({}): {}",
+                                    value.entry_name, value.entry_type, err
+                                ),
+                            }
+                        ),
+                        Err(err) => println!("{err}"),
+                    }
+                }
+            }
+            // Only remember the records that were updated, because we want to
+            // retry updates in case the last update just happened to fail
+            for (result, (entry, entry_type, fqdn, rrset)) in results.iter().zip(pending) {
+                let (status, message, cause) = match result {
+                    Ok(ResponseFeedback {
+                        response: Ok(message),
+                        changed,
+                        cause,
+                        ..
+                    }) => (
+                        match (changed, opts.dry_run) {
+                            (false, _) => RecordStatus::UpToDate,
+                            (true, false) => RecordStatus::Updated,
+                            (true, true) => RecordStatus::WouldUpdate,
+                        },
+                        message.clone(),
+                        cause.clone(),
                     ),
-                    Err(err) => println!("{err}"),
+                    Ok(ResponseFeedback {
+                        response: Err(err), ..
+                    }) => (RecordStatus::Failed, err.to_string(), None),
+                    Err(err) => (RecordStatus::Failed, err.to_string(), None),
+                };
+                if matches!(status, RecordStatus::Updated | RecordStatus::UpToDate) {
+                    state.set(&fqdn, &entry.name, entry_type, rrset.values, rrset.ttl);
                 }
+                records.push(RecordReport {
+                    entry_name: entry.name.clone(),
+                    entry_type: entry_type.to_string(),
+                    fqdn,
+                    status,
+                    message: Some(message),
+                    cause,
+                });
             }
-            if results
-                .iter()
-                // all tasks finished OK, and all responses were OK as well
-                .all(|result| result.as_ref().map(|v| v.response.is_ok()).unwrap_or(false))
-            {
-                // Only then we update the last seen IP, because we want to
-                // retry updates in case the last update just happened to fail
-                last_ipv4 = ipv4.ok().map(|v| v.to_string());
-                last_ipv6 = ipv6.ok().map(|v| v.to_string());
-            } else if opts.repeat.is_some() {
-                println!("Some operations failed. They will be retried during the next repeat.")
+            // Nothing is changed in a dry run, so there's nothing to remember
+            if let Some(path) = state_path.as_ref().filter(|_| !opts.dry_run) {
+                if let Err(err) = state.save(path) {
+                    eprintln!(
+                        "Failed to save the state file {}: {err}",
+                        path.to_string_lossy()
+                    );
+                }
             }
-        } else {
+        } else if text && first_error.is_none() {
             println!("IP address has not changed since last update");
         }
+        let all_ok = records
+            .iter()
+            .all(|record| record.status != RecordStatus::Failed);
+        let repeating = opts.repeat.is_some() || opts.watch;
+        if text && !all_ok && repeating {
+            println!("Some operations failed. They will be retried during the next repeat.")
+        }
+
+        if !text {
+            let report = RunReport {
+                ipv4: IpReport::new(ipv4_result.as_ref()),
+                ipv6: IpReport::new(ipv6_result.as_ref()),
+                success: all_ok,
+                records,
+            };
+            println!(
+                "{}",
+                serde_json::to_string(&report).die("Failed to serialize the report")
+            );
+        }
 
+        if let Some(watcher) = &mut watcher {
+            let poll = opts.repeat.unwrap_or(watch::WATCH_POLL);
+            let poll_sleep = sleep(Duration::from_secs(poll));
+            tokio::pin!(poll_sleep);
+            select! {
+                changed = watcher.changed() => match changed {
+                    Ok(()) => {
+                        if text {
+                            println!("The network addresses changed, checking again");
+                        }
+                    }
+                    // Reading the changes can fail for reasons that go away,
+                    // keep running and check again when the poll is due
+                    Err(err) => {
+                        eprintln!("{err}, checking again in {poll} seconds");
+                        poll_sleep.await;
+                    }
+                },
+                _ = &mut poll_sleep => {}
+            }
+            continue;
+        }
         if let Some(repeat) = opts.repeat {
             // If configured to repeat, do so
             sleep(Duration::from_secs(repeat)).await;
             continue;
         }
-        // Otherwise this is one-shot, we should exit now
+        // Otherwise this is one-shot, we should exit now. Not finding the
+        // address is still an error, so the exit status shows it.
+        if let Some(err) = first_error {
+            return Err(err);
+        }
         break;
     }
 
-    Ok(())
+    Ok(any_changed)
+}
+
+fn make_ip_source(name: &IPSourceName) -> Result<Box<dyn IPSource>, ConfigError> {
+    Ok(match name {
+        IPSourceName::Ipify => Box::new(IPSourceIpify),
+        IPSourceName::Icanhazip => Box::new(IPSourceIcanhazip),
+        IPSourceName::SeeIP => Box::new(IPSourceSeeIP),
+        IPSourceName::Interface(interface) => Box::new(IPSourceInterface {
+            interface: interface.clone(),
+        }),
+        IPSourceName::Custom(custom) => Box::new(IPSourceCustom::new(custom)?),
+        IPSourceName::Dns(dns) => Box::new(IPSourceDns {
+            config: dns.clone(),
+        }),
+        IPSourceName::Stun(stun) => Box::new(IPSourceStun {
+            config: stun.clone(),
+        }),
+        IPSourceName::Gateway(gateway) => Box::new(IPSourceGateway {
+            config: gateway.clone(),
+        }),
+        IPSourceName::Command(command) => Box::new(IPSourceCommand {
+            command: command.clone(),
+            timeout: COMMAND_TIMEOUT,
+        }),
+        IPSourceName::File(path) => Box::new(IPSourceFile { path: path.clone() }),
+    })
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -280,24 +527,64 @@ async fn main() -> anyhow::Result<()> {
     let opts = opts::Opts::parse();
     let conf = config::load_config(&opts)?;
 
-    let ip_source: Box<dyn IPSource> = match conf.ip_source {
-        IPSourceName::Ipify => Box::new(IPSourceIpify),
-        IPSourceName::Icanhazip => Box::new(IPSourceIcanhazip),
-        IPSourceName::SeeIP => Box::new(IPSourceSeeIP),
-    };
     config::validate_config(&conf)?;
-    run("https://api.gandi.net", &ip_source, &conf, &opts).await?;
+    if let Some(command) = &opts.command {
+        match command {
+            Command::SetTxt(args) => {
+                let (domain, validation) = acme::challenge(args)?;
+                acme::ChallengeHook::new(&conf)?
+                    .set_txt(&domain, &validation)
+                    .await?;
+            }
+            Command::ClearTxt(args) => {
+                let (domain, validation) = acme::challenge(args)?;
+                acme::ChallengeHook::new(&conf)?
+                    .clear_txt(&domain, &validation)
+                    .await?;
+            }
+            Command::Serve(args) => serve::serve(&conf, args.listen).await?,
+        }
+        return Ok(());
+    }
+    let mut sources = conf
+        .ip_source
+        .iter()
+        .map(make_ip_source)
+        .collect::<Result<Vec<_>, _>>()?;
+    let ip_source: Box<dyn IPSource> = if sources.len() == 1 {
+        sources.remove(0)
+    } else {
+        Box::new(IPSourceMulti {
+            sources,
+            strategy: match conf.ip_source_mode {
+                IPSourceMode::Fallback => Strategy::Fallback,
+                IPSourceMode::Quorum => Strategy::Quorum(Config::ip_source_quorum(&conf)),
+            },
+        })
+    };
+    let changed = run(&ip_source, &conf, &opts).await?;
+    if opts.dry_run && changed {
+        // Like `terraform plan -detailed-exitcode`, so scripts can tell that
+        // there are changes pending
+        std::process::exit(2);
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{config, ip_source::common::IPSource, opts::Opts, run, ClientError};
+    use crate::{
+        config,
+        ip_source::common::IPSource,
+        opts::{Opts, OutputFormat},
+        run, ClientError,
+    };
     use async_trait::async_trait;
     use httpmock::MockServer;
     use lazy_static::lazy_static;
     use std::{
         env::temp_dir,
+        net::{Ipv4Addr, Ipv6Addr},
         sync::atomic::{AtomicBool, Ordering::SeqCst},
         time::Duration,
     };
@@ -307,11 +594,16 @@ mod tests {
 
     #[async_trait]
     impl IPSource for IPSourceMock {
-        async fn get_ipv4(&self) -> Result<String, ClientError> {
-            Ok("192.168.0.0".to_string())
+        async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+            Ok(Ipv4Addr::new(192, 168, 0, 0))
+        }
+        async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+            Ok(Ipv6Addr::new(
+                0xfe80, 0, 0, 0, 0x0208, 0x74ff, 0xfeda, 0x625c,
+            ))
         }
-        async fn get_ipv6(&self) -> Result<String, ClientError> {
-            Ok("fe80:0000:0208:74ff:feda:625c".to_string())
+        fn name(&self) -> String {
+            "mock".to_string()
         }
     }
 
@@ -346,9 +638,10 @@ mod tests {
             config: Some(temp.to_string_lossy().to_string()),
             ..Opts::default()
         };
-        let conf = config::load_config(&opts).expect("Failed to load config");
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
         let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
-        run(server.base_url().as_str(), &ip_source, &conf, &opts)
+        run(&ip_source, &conf, &opts)
             .await
             .expect("Failed when running the update");
 
@@ -397,9 +690,10 @@ mod tests {
                     repeat: Some(1),
                     ..Opts::default()
                 };
-                let conf = config::load_config(&opts).expect("Failed to load config");
+                let mut conf = config::load_config(&opts).expect("Failed to load config");
+                conf.gandi.api_url = server_url.clone();
                 let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
-                run(&server_url, &ip_source, &conf, &opts)
+                run(&ip_source, &conf, &opts)
                     .await
                     .expect("Failed when running the update");
             });
@@ -427,7 +721,9 @@ mod tests {
             temp.push("test.toml");
             fs::write(
                 &temp,
-                "fqdn = \"example.com\"\napi_key = \"xxx\"\nttl = 300\n[[entry]]\nname =\"@\"\n",
+                // Without a retry budget the failure is only retried by the
+                // next repeat
+                "fqdn = \"example.com\"\napi_key = \"xxx\"\nttl = 300\nretry_budget = 0\n[[entry]]\nname =\"@\"\n",
             )
             .await
             .expect("Failed to write test config file");
@@ -473,9 +769,10 @@ mod tests {
                     repeat: Some(1),
                     ..Opts::default()
                 };
-                let conf = config::load_config(&opts).expect("Failed to load config");
+                let mut conf = config::load_config(&opts).expect("Failed to load config");
+                conf.gandi.api_url = server_url.clone();
                 let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
-                run(&server_url, &ip_source, &conf, &opts)
+                run(&ip_source, &conf, &opts)
                     .await
                     .expect("Failed when running the update");
             });
@@ -485,8 +782,8 @@ mod tests {
 
             // The first call failed
             mock_fail.assert();
-            // We then retried since the first call failed. The retry succeeds
-            // so we don't retry again.
+            // The next repeat retried since the first call failed. The retry
+            // succeeds so we don't retry again.
             mock.assert();
         });
     }
@@ -531,9 +828,10 @@ mod tests {
                     repeat: Some(1),
                     ..Opts::default()
                 };
-                let conf = config::load_config(&opts).expect("Failed to load config");
+                let mut conf = config::load_config(&opts).expect("Failed to load config");
+                conf.gandi.api_url = server_url.clone();
                 let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
-                run(&server_url, &ip_source, &conf, &opts)
+                run(&ip_source, &conf, &opts)
                     .await
                     .expect("Failed when running the update");
             });
@@ -545,4 +843,453 @@ mod tests {
             assert!(mock.hits() > 1);
         });
     }
+
+    #[tokio::test]
+    async fn skip_up_to_date() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\nttl = 300\n[[entry]]\nname =\"@\"\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let get_mock = server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.com/records/@/A");
+            then.status(200).body(
+                "{\"rrset_name\":\"@\", \"rrset_type\":\"A\", \"rrset_ttl\":300, \"rrset_values\":[\"192.168.0.0\"]}",
+            );
+        });
+        let put_mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        get_mock.assert();
+        // The record already has the right address and TTL
+        put_mock.assert_hits(0);
+    }
+
+    #[tokio::test]
+    async fn update_changed_ttl() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\nttl = 300\n[[entry]]\nname =\"@\"\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let get_mock = server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.com/records/@/A");
+            then.status(200).body(
+                "{\"rrset_name\":\"@\", \"rrset_type\":\"A\", \"rrset_ttl\":600, \"rrset_values\":[\"192.168.0.0\"]}",
+            );
+        });
+        let put_mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A")
+                .body_contains("300");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        get_mock.assert();
+        put_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn skip_published_with_state_file() {
+        let dir = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&dir)
+            .await
+            .expect("Failed to create test dir");
+        let state_file = dir.join("state-run.json");
+        let _ = fs::remove_file(&state_file).await;
+        let temp = dir.join("test-state.toml");
+        fs::write(
+            &temp,
+            format!(
+                "fqdn = \"example.com\"\napi_key = \"xxx\"\nstate_file = {:?}\n[[entry]]\nname =\"@\"\n",
+                state_file.to_string_lossy()
+            ),
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A")
+                .body_contains("192.168.0.0");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        // Two separate one-shot runs, the second one only knows about the
+        // first through the state file
+        for _ in 0..2 {
+            run(&ip_source, &conf, &opts)
+                .await
+                .expect("Failed when running the update");
+        }
+
+        mock.assert();
+        assert!(fs::metadata(&state_file).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn personal_access_token() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-pat.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\nauth_type = \"Pat\"\n[[entry]]\nname =\"@\"\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A")
+                .header("Authorization", "Bearer xxx");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-dry-run.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\n[[entry]]\nname =\"@\"\n[[entry]]\nname =\"www\"\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let get_mock = server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.com/records/@/A");
+            then.status(200).body(
+                "{\"rrset_name\":\"@\", \"rrset_type\":\"A\", \"rrset_ttl\":300, \"rrset_values\":[\"192.168.0.1\"]}",
+            );
+        });
+        let put_mock = server.mock(|when, then| {
+            when.method("PUT");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            dry_run: true,
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        let changed = run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        // One record has the wrong address and the other one is missing
+        assert!(changed);
+        get_mock.assert();
+        put_mock.assert_hits(0);
+    }
+
+    #[tokio::test]
+    async fn json_output() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\nttl = 300\n[[entry]]\nname =\"@\"\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"DNS Record Created\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            output: OutputFormat::Json,
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        let changed = run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        assert!(changed);
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn static_txt_record() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-txt.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\n[[entry]]\nname =\"@\"\ntype = \"TXT\"\nvalues = [\"v=spf1 -all\"]\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/TXT")
+                .body_contains("\\\"v=spf1 -all\\\"");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        config::validate_config(&conf).expect("Config should be valid");
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        mock.assert();
+    }
+
+    struct IPSourceNoIpv6;
+
+    #[async_trait]
+    impl IPSource for IPSourceNoIpv6 {
+        async fn get_ipv4(&self) -> Result<Ipv4Addr, ClientError> {
+            Ok(Ipv4Addr::new(192, 168, 0, 0))
+        }
+        async fn get_ipv6(&self) -> Result<Ipv6Addr, ClientError> {
+            Err(ClientError::AllSourcesFailed("no route".to_string()))
+        }
+        fn name(&self) -> String {
+            "mock".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_address() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-missing.toml");
+        fs::write(
+            &temp,
+            "fqdn = \"example.com\"\napi_key = \"xxx\"\n[[entry]]\nname =\"@\"\ntypes = [\"AAAA\"]\n[[entry]]\nname =\"@\"\ntype = \"TXT\"\nvalues = [\"v=spf1 -all\"]\n",
+        )
+        .await
+        .expect("Failed to write test config file");
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/TXT");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let mut conf = config::load_config(&opts).expect("Failed to load config");
+        conf.gandi.api_url = server.base_url();
+        config::validate_config(&conf).expect("Config should be valid");
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceNoIpv6);
+        let result = run(&ip_source, &conf, &opts).await;
+
+        // The TXT record is still updated, but the run reports the failure
+        assert!(matches!(result, Err(ClientError::Ipv6missing(..))));
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn mixed_providers() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-providers.toml");
+        let home = MockServer::start();
+        let work = MockServer::start();
+        fs::write(
+            &temp,
+            format!(
+                "fqdn = \"example.com\"\napi_key = \"xxx\"\napi_url = \"{}\"\n[providers.work]\ntype = \"gandi\"\napi_key = \"zzz\"\napi_url = \"{}\"\n[[entry]]\nname = \"@\"\n[[entry]]\nname = \"vpn\"\nfqdn = \"example.org\"\nprovider = \"work\"\n",
+                home.base_url(),
+                work.base_url()
+            ),
+        )
+        .await
+        .expect("Failed to write test config file");
+        let home_mock = home.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/@/A")
+                .header("Authorization", "Apikey xxx");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+        let work_mock = work.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.org/records/vpn/A")
+                .header("Authorization", "Apikey zzz");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = config::load_config(&opts).expect("Failed to load config");
+        config::validate_config(&conf).expect("Config should be valid");
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        home_mock.assert();
+        work_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn cloudflare_provider() {
+        let mut temp = temp_dir().join("gandi-live-dns-test");
+        fs::create_dir_all(&temp)
+            .await
+            .expect("Failed to create test dir");
+        temp.push("test-cloudflare.toml");
+        let server = MockServer::start();
+        fs::write(
+            &temp,
+            format!(
+                "fqdn = \"example.com\"\n[providers.cf]\ntype = \"cloudflare\"\napi_token = \"ttt\"\napi_url = \"{}\"\n[[entry]]\nname = \"www\"\nprovider = \"cf\"\n",
+                server.base_url()
+            ),
+        )
+        .await
+        .expect("Failed to write test config file");
+        let zone_mock = server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones")
+                .query_param("name", "example.com")
+                .header("Authorization", "Bearer ttt");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":[{\"id\":\"zone1\",\"name\":\"example.com\"}]}",
+            );
+        });
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones/zone1/dns_records")
+                .query_param("type", "A")
+                .query_param("name", "www.example.com");
+            then.status(200)
+                .body("{\"success\":true,\"errors\":[],\"result\":[]}");
+        });
+        let create_mock = server.mock(|when, then| {
+            when.method("POST")
+                .path("/zones/zone1/dns_records")
+                .json_body_partial("{\"type\":\"A\",\"name\":\"www.example.com\",\"content\":\"192.168.0.0\",\"ttl\":300}");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":{\"id\":\"rec1\"}}",
+            );
+        });
+
+        let opts = Opts {
+            config: Some(temp.to_string_lossy().to_string()),
+            ..Opts::default()
+        };
+        let conf = config::load_config(&opts).expect("Failed to load config");
+        config::validate_config(&conf).expect("Config should be valid");
+        let ip_source: Box<dyn IPSource> = Box::new(IPSourceMock);
+        let changed = run(&ip_source, &conf, &opts)
+            .await
+            .expect("Failed when running the update");
+
+        assert!(changed);
+        // The zone ID is looked up once, for both the check and the update
+        zone_mock.assert_hits(1);
+        create_mock.assert();
+    }
 }
@@ -1,11 +1,21 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Progress and results for people to read.
+    #[default]
+    Text,
+    /// A JSON document with the results of each run, on a single line.
+    Json,
+}
 
 /// A tool to automatically update DNS entries on Gandi, using it as a dynamic DNS system.
 #[derive(Parser, Debug, Default)]
 #[clap(author, version, about, long_about = None, name = "gandi-live-dns")]
 pub struct Opts {
     /// The path to the configuration file.
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub config: Option<String>,
     /// Skip IPv4 updates.
     ///
@@ -27,4 +37,69 @@ pub struct Opts {
     /// `nohup` to keep it running in the background.
     #[clap(long)]
     pub repeat: Option<u64>,
+    /// Update as soon as the addresses of this machine change.
+    ///
+    /// Listens for address and default route changes from the kernel, and
+    /// runs the updates a couple of seconds after one. Still checks every
+    /// `--repeat` seconds, or every 30 minutes if that's not given, to catch
+    /// changes that don't happen on this machine, like a new address on the
+    /// router in front of it. Only supported on Linux.
+    #[clap(action, long, conflicts_with = "dry_run")]
+    pub watch: bool,
+    /// Show what would change, without changing anything.
+    ///
+    /// Finds out the IP address and compares it with the records on Gandi,
+    /// then prints the changes that would be made. Exits with status 2 if
+    /// there are any changes, and 0 if everything is already up to date.
+    #[clap(action, long, conflicts_with = "repeat")]
+    pub dry_run: bool,
+    /// How to print the results.
+    ///
+    /// With `json`, each run prints a single line with the detected IP
+    /// addresses and the result for every record. Errors are still printed
+    /// to stderr.
+    #[clap(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Add the TXT record for an ACME DNS-01 challenge.
+    ///
+    /// Use this as certbot's `--manual-auth-hook`, or as lego's exec
+    /// provider which calls it as `present`.
+    #[clap(alias = "present")]
+    SetTxt(ChallengeArgs),
+    /// Remove the TXT record for an ACME DNS-01 challenge.
+    ///
+    /// Use this as certbot's `--manual-cleanup-hook`, or as lego's exec
+    /// provider which calls it as `cleanup`.
+    #[clap(alias = "cleanup")]
+    ClearTxt(ChallengeArgs),
+    /// Accept updates from routers and other dyndns2 clients.
+    ///
+    /// Listens for HTTP requests to `/nic/update`, and publishes the
+    /// addresses of the hosts in the `serve` section of the config.
+    Serve(ServeArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The address and port to listen on, instead of the one in the config.
+    #[clap(long)]
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Args, Debug)]
+pub struct ChallengeArgs {
+    /// The domain being validated, or the full name of the challenge record.
+    ///
+    /// Defaults to `CERTBOT_DOMAIN`.
+    pub domain: Option<String>,
+    /// The validation value to put in the record.
+    ///
+    /// Defaults to `CERTBOT_VALIDATION`.
+    pub validation: Option<String>,
 }
@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{header, Client, ClientBuilder, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{absolute, owner, relative, send, DnsProvider, Published, Rrset};
+use crate::config::CloudflareConfig;
+use crate::{records, ClientError};
+
+/// 1200 requests per 5 minutes, see
+/// https://developers.cloudflare.com/fundamentals/api/reference/limits/
+const CLOUDFLARE_RATE_LIMIT: u32 = 240;
+
+fn api_client(api_token: &str) -> Result<Client, ClientError> {
+    let mut auth_value = header::HeaderValue::from_str(&format!("Bearer {api_token}"))?;
+    auth_value.set_sensitive(true);
+    let mut headers = header::HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, auth_value);
+    Ok(ClientBuilder::new().default_headers(headers).build()?)
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    code: i64,
+    message: String,
+}
+
+/// Every response from the API is wrapped in this.
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ResponseMessage>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct Zone {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct DnsRecord {
+    id: String,
+    content: String,
+    ttl: u32,
+    priority: Option<u16>,
+}
+
+#[derive(Serialize)]
+struct RecordPayload<'p> {
+    #[serde(rename = "type")]
+    record_type: &'p str,
+    name: &'p str,
+    content: String,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+}
+
+/// Cloudflare DNS, see https://developers.cloudflare.com/api/
+pub(crate) struct Cloudflare {
+    client: Client,
+    base_url: String,
+    proxied: Option<bool>,
+    /// How long to keep retrying requests that failed because the API was
+    /// overloaded or couldn't be reached.
+    retry_budget: Duration,
+    /// The IDs of the zones looked up so far, by name.
+    zone_ids: Mutex<BTreeMap<String, String>>,
+}
+
+impl Cloudflare {
+    pub(crate) fn new(
+        config: &CloudflareConfig,
+        retry_budget: Duration,
+    ) -> Result<Cloudflare, ClientError> {
+        Ok(Cloudflare {
+            client: api_client(&config.api_token)?,
+            base_url: config.api_url.trim_end_matches('/').to_string(),
+            proxied: config.proxied,
+            retry_budget,
+            zone_ids: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Sends the request, and unwraps the result from the response.
+    async fn request<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, ClientError> {
+        let resp = send(request, self.retry_budget).await?;
+        let status = resp.status();
+        let text = resp.text().await?;
+        match serde_json::from_str::<ApiResponse<T>>(&text) {
+            Ok(ApiResponse {
+                success: true,
+                result: Some(result),
+                ..
+            }) if status.is_success() => Ok(result),
+            Ok(body) => Err(ClientError::Cloudflare(
+                status,
+                body.errors
+                    .iter()
+                    .map(|error| format!("{} ({})", error.message, error.code))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )),
+            // Errors from proxies in front of the API may not be JSON
+            Err(_) => Err(ClientError::Cloudflare(status, text)),
+        }
+    }
+
+    async fn zone_id(&self, zone: &str) -> Result<String, ClientError> {
+        if let Some(id) = self.zone_ids.lock().unwrap().get(zone) {
+            return Ok(id.clone());
+        }
+        let url = format!("{}/zones", self.base_url);
+        let zones: Vec<Zone> = self
+            .request(self.client.get(url).query(&[("name", zone)]))
+            .await?;
+        let id = zones
+            .into_iter()
+            .next()
+            .ok_or_else(|| ClientError::ZoneNotFound(zone.to_string()))?
+            .id;
+        self.zone_ids
+            .lock()
+            .unwrap()
+            .insert(zone.to_string(), id.clone());
+        Ok(id)
+    }
+
+    /// The records of one name and type. Cloudflare keeps each value as a
+    /// separate record.
+    async fn records(
+        &self,
+        zone_id: &str,
+        owner: &str,
+        rrset_type: &str,
+    ) -> Result<Vec<DnsRecord>, ClientError> {
+        let url = format!("{}/zones/{zone_id}/dns_records", self.base_url);
+        self.request(self.client.get(url).query(&[
+            ("type", rrset_type),
+            ("name", owner),
+            ("per_page", "100"),
+        ]))
+        .await
+    }
+
+    fn record_url(&self, zone_id: &str, record_id: &str) -> String {
+        format!("{}/zones/{zone_id}/dns_records/{record_id}", self.base_url)
+    }
+}
+
+/// Checks that Cloudflare can hold records of this type with the values
+/// written the way the config has them.
+fn check_type(rrset_type: &str) -> Result<(), ClientError> {
+    match rrset_type {
+        "A" | "AAAA" | "CNAME" | "MX" | "TXT" => Ok(()),
+        _ => Err(ClientError::UnsupportedType(
+            "Cloudflare".to_string(),
+            rrset_type.to_string(),
+        )),
+    }
+}
+
+/// Turns a value written the way it is in the config into the content and
+/// priority of a record.
+fn to_content(zone: &str, rrset_type: &str, value: &str) -> Result<(String, Option<u16>), String> {
+    Ok(match rrset_type {
+        "CNAME" => (absolute(zone, value), None),
+        "MX" => {
+            let (priority, host) = value
+                .split_once(' ')
+                .ok_or("expected a priority and a host")?;
+            let priority = priority
+                .parse()
+                .map_err(|_| "the priority is not a number")?;
+            (absolute(zone, host.trim()), Some(priority))
+        }
+        _ => (value.to_string(), None),
+    })
+}
+
+/// The opposite of `to_content`, so the records can be compared with the
+/// values we want.
+fn from_content(zone: &str, rrset_type: &str, record: &DnsRecord) -> String {
+    match rrset_type {
+        "CNAME" => relative(zone, &record.content),
+        "MX" => format!(
+            "{} {}",
+            record.priority.unwrap_or_default(),
+            relative(zone, &record.content)
+        ),
+        "TXT" => records::api_value("TXT", &record.content),
+        _ => record.content.clone(),
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        check_type(rrset_type)?;
+        let zone_id = self.zone_id(zone).await?;
+        let records = self
+            .records(&zone_id, &owner(zone, name), rrset_type)
+            .await?;
+        let Some(first) = records.first() else {
+            return Ok(None);
+        };
+        Ok(Some(Rrset {
+            values: records
+                .iter()
+                .map(|record| from_content(zone, rrset_type, record))
+                .collect(),
+            ttl: first.ttl,
+        }))
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        check_type(rrset_type)?;
+        let zone_id = self.zone_id(zone).await?;
+        let owner = owner(zone, name);
+        let existing = self.records(&zone_id, &owner, rrset_type).await?;
+        // Reuse the records that are there, then add or remove records until
+        // there is one for each value
+        for (index, value) in rrset.values.iter().enumerate() {
+            let (content, priority) = to_content(zone, rrset_type, value).map_err(|reason| {
+                ClientError::Rdata(rrset_type.to_string(), value.clone(), reason)
+            })?;
+            let payload = RecordPayload {
+                record_type: rrset_type,
+                name: &owner,
+                content,
+                ttl: rrset.ttl,
+                priority,
+                proxied: self.proxied,
+            };
+            let request = match existing.get(index) {
+                Some(record) => self
+                    .client
+                    .patch(self.record_url(&zone_id, &record.id))
+                    .json(&payload),
+                None => self
+                    .client
+                    .post(format!("{}/zones/{zone_id}/dns_records", self.base_url))
+                    .json(&payload),
+            };
+            self.request::<serde_json::Value>(request).await?;
+        }
+        for record in existing.iter().skip(rrset.values.len()) {
+            self.request::<serde_json::Value>(
+                self.client.delete(self.record_url(&zone_id, &record.id)),
+            )
+            .await?;
+        }
+        Ok(Published {
+            message: if existing.is_empty() {
+                "DNS record created".to_string()
+            } else {
+                "DNS record updated".to_string()
+            },
+            cause: None,
+        })
+    }
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        check_type(rrset_type)?;
+        let zone_id = self.zone_id(zone).await?;
+        for record in self
+            .records(&zone_id, &owner(zone, name), rrset_type)
+            .await?
+        {
+            self.request::<serde_json::Value>(
+                self.client.delete(self.record_url(&zone_id, &record.id)),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        NonZeroU32::new(CLOUDFLARE_RATE_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+
+    use std::time::Duration;
+
+    use super::Cloudflare;
+    use crate::{
+        config::CloudflareConfig,
+        provider::{DnsProvider, Rrset},
+        ClientError,
+    };
+
+    fn provider(server: &MockServer) -> Cloudflare {
+        provider_with_retries(server, Duration::ZERO)
+    }
+
+    fn provider_with_retries(server: &MockServer, retry_budget: Duration) -> Cloudflare {
+        Cloudflare::new(
+            &CloudflareConfig {
+                api_token: "ttt".to_string(),
+                api_token_file: None,
+                api_token_env: None,
+                proxied: None,
+                api_url: server.base_url(),
+            },
+            retry_budget,
+        )
+        .expect("Failed to create provider")
+    }
+
+    fn zone_mock(server: &MockServer) {
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones")
+                .query_param("name", "example.com")
+                .header("Authorization", "Bearer ttt");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":[{\"id\":\"zone1\",\"name\":\"example.com\"}]}",
+            );
+        });
+    }
+
+    #[tokio::test]
+    async fn get_rrset_test() {
+        let server = MockServer::start();
+        zone_mock(&server);
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones/zone1/dns_records")
+                .query_param("type", "MX")
+                .query_param("name", "example.com");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":[{\"id\":\"rec1\",\"type\":\"MX\",\"name\":\"example.com\",\"content\":\"mail.example.com\",\"priority\":10,\"ttl\":300}]}",
+            );
+        });
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones/zone1/dns_records")
+                .query_param("type", "A");
+            then.status(200)
+                .body("{\"success\":true,\"errors\":[],\"result\":[]}");
+        });
+
+        let provider = provider(&server);
+        let rrset = provider
+            .get_rrset("example.com", "@", "MX")
+            .await
+            .expect("Failed to get records");
+        assert_eq!(
+            rrset,
+            Some(Rrset {
+                values: vec!["10 mail".to_string()],
+                ttl: 300,
+            })
+        );
+        let rrset = provider
+            .get_rrset("example.com", "www", "A")
+            .await
+            .expect("Failed to get records");
+        assert_eq!(rrset, None);
+    }
+
+    #[tokio::test]
+    async fn set_rrset_test() {
+        let server = MockServer::start();
+        zone_mock(&server);
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/zones/zone1/dns_records")
+                .query_param("type", "A")
+                .query_param("name", "www.example.com");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":[{\"id\":\"rec1\",\"type\":\"A\",\"name\":\"www.example.com\",\"content\":\"93.184.216.35\",\"ttl\":300},{\"id\":\"rec2\",\"type\":\"A\",\"name\":\"www.example.com\",\"content\":\"93.184.216.36\",\"ttl\":300}]}",
+            );
+        });
+        let patch = server.mock(|when, then| {
+            when.method("PATCH")
+                .path("/zones/zone1/dns_records/rec1")
+                .json_body_partial("{\"type\":\"A\",\"name\":\"www.example.com\",\"content\":\"93.184.216.34\",\"ttl\":600}");
+            then.status(200).body(
+                "{\"success\":true,\"errors\":[],\"result\":{\"id\":\"rec1\"}}",
+            );
+        });
+        let delete = server.mock(|when, then| {
+            when.method("DELETE").path("/zones/zone1/dns_records/rec2");
+            then.status(200)
+                .body("{\"success\":true,\"errors\":[],\"result\":{\"id\":\"rec2\"}}");
+        });
+
+        let rrset = Rrset {
+            values: vec!["93.184.216.34".to_string()],
+            ttl: 600,
+        };
+        let published = provider(&server)
+            .set_rrset("example.com", "www", "A", &rrset)
+            .await
+            .expect("Failed to set records");
+        assert_eq!(published.message, "DNS record updated");
+        patch.assert();
+        delete.assert();
+    }
+
+    #[tokio::test]
+    async fn create_not_retried_test() {
+        let server = MockServer::start();
+        zone_mock(&server);
+        server.mock(|when, then| {
+            when.method("GET").path("/zones/zone1/dns_records");
+            then.status(200)
+                .body("{\"success\":true,\"errors\":[],\"result\":[]}");
+        });
+        // The record may have been created even though the response says
+        // otherwise, so sending it again could create a second one
+        let create = server.mock(|when, then| {
+            when.method("POST").path("/zones/zone1/dns_records");
+            then.status(502).body("<html>Bad Gateway</html>");
+        });
+
+        let rrset = Rrset {
+            values: vec!["93.184.216.34".to_string()],
+            ttl: 600,
+        };
+        let result = provider_with_retries(&server, Duration::from_secs(5))
+            .set_rrset("example.com", "www", "A", &rrset)
+            .await;
+        assert!(matches!(result, Err(ClientError::Cloudflare(_, _))));
+        create.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn errors_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/zones");
+            then.status(200)
+                .body("{\"success\":true,\"errors\":[],\"result\":[]}");
+        });
+        let result = provider(&server).get_rrset("example.com", "www", "A").await;
+        assert!(matches!(result, Err(ClientError::ZoneNotFound(_))));
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET").path("/zones");
+            then.status(403).body(
+                "{\"success\":false,\"errors\":[{\"code\":9109,\"message\":\"Invalid access token\"}],\"result\":null}",
+            );
+        });
+        let result = provider(&server).get_rrset("example.com", "www", "A").await;
+        match result {
+            Err(ClientError::Cloudflare(status, message)) => {
+                assert_eq!(status.as_u16(), 403);
+                assert_eq!(message, "Invalid access token (9109)");
+            }
+            other => panic!("Expected a Cloudflare error, got {other:?}"),
+        }
+
+        let result = provider(&server).get_rrset("example.com", "@", "CAA").await;
+        assert!(matches!(result, Err(ClientError::UnsupportedType(_, _))));
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use reqwest::{header, Client, ClientBuilder, StatusCode};
+
+use super::{owner, send, DnsProvider, Published, Rrset};
+use crate::config::Dyndns2Config;
+use crate::{ClientError, Dyndns2Error};
+
+/// Services ask clients to identify themselves, and block ones that don't.
+const USER_AGENT: &str = concat!("gandi-live-dns/", env!("CARGO_PKG_VERSION"));
+/// How long to wait after the service said it has a problem, the protocol
+/// asks for at least half an hour.
+const SERVER_ERROR_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// Reads the answer to an update. Only one host is sent at a time, so only
+/// the first line matters.
+fn parse_answer(body: &str) -> Result<String, Dyndns2Error> {
+    let answer = body.lines().next().unwrap_or_default().trim();
+    let code = answer.split_whitespace().next().unwrap_or_default();
+    match code {
+        "good" | "nochg" => Ok(answer.to_string()),
+        "badauth" => Err(Dyndns2Error::BadAuth()),
+        "abuse" => Err(Dyndns2Error::Abuse()),
+        "nohost" => Err(Dyndns2Error::NoHost()),
+        "notfqdn" => Err(Dyndns2Error::NotFqdn()),
+        "badagent" => Err(Dyndns2Error::BadAgent()),
+        "!donator" => Err(Dyndns2Error::NotDonator()),
+        "911" | "dnserr" => Err(Dyndns2Error::ServerError(code.to_string())),
+        _ => Err(Dyndns2Error::Unknown(answer.to_string())),
+    }
+}
+
+/// A service that takes updates with the dyndns2 protocol, like No-IP or
+/// Dynu. The protocol can only set addresses, there is no way to read them
+/// back, so this remembers what it sent.
+pub(crate) struct Dyndns2 {
+    client: Client,
+    url: String,
+    username: String,
+    password: String,
+    retry_budget: Duration,
+    /// What was last sent for each host and type.
+    sent: Mutex<BTreeMap<(String, String), Rrset>>,
+    /// Services expect clients to stop after some answers until someone
+    /// fixes the problem, and may block the account if they don't. Most are
+    /// about the account, but nohost and notfqdn only stop that host.
+    stopped: Mutex<Option<Dyndns2Error>>,
+    stopped_hosts: Mutex<BTreeMap<String, Dyndns2Error>>,
+    /// After a server error, no updates are sent until then.
+    paused: Mutex<Option<(Instant, Dyndns2Error)>>,
+}
+
+impl Dyndns2 {
+    pub(crate) fn new(
+        config: &Dyndns2Config,
+        retry_budget: Duration,
+    ) -> Result<Dyndns2, ClientError> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            header::HeaderValue::from_static(USER_AGENT),
+        );
+        Ok(Dyndns2 {
+            client: ClientBuilder::new().default_headers(headers).build()?,
+            url: config.url.clone(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            retry_budget,
+            sent: Mutex::new(BTreeMap::new()),
+            stopped: Mutex::new(None),
+            stopped_hosts: Mutex::new(BTreeMap::new()),
+            paused: Mutex::new(None),
+        })
+    }
+}
+
+fn check_type(rrset_type: &str) -> Result<(), ClientError> {
+    match rrset_type {
+        "A" | "AAAA" => Ok(()),
+        _ => Err(ClientError::UnsupportedType(
+            "dyndns2".to_string(),
+            rrset_type.to_string(),
+        )),
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Dyndns2 {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        check_type(rrset_type)?;
+        let key = (owner(zone, name), rrset_type.to_string());
+        Ok(self.sent.lock().unwrap().get(&key).cloned())
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        check_type(rrset_type)?;
+        let hostname = owner(zone, name);
+        if let Some(err) = self.stopped.lock().unwrap().clone() {
+            return Err(err.into());
+        }
+        if let Some(err) = self.stopped_hosts.lock().unwrap().get(&hostname).cloned() {
+            return Err(err.into());
+        }
+        if let Some((until, err)) = self.paused.lock().unwrap().clone() {
+            if Instant::now() < until {
+                return Err(err.into());
+            }
+        }
+        let request = self
+            .client
+            .get(&self.url)
+            .basic_auth(&self.username, Some(&self.password))
+            .query(&[
+                ("hostname", hostname.as_str()),
+                ("myip", &rrset.values.join(",")),
+            ]);
+        let resp = send(request, self.retry_budget).await?;
+        // Some services answer badauth with a 401 and nothing else
+        let answer = match resp.status() {
+            StatusCode::UNAUTHORIZED => Err(Dyndns2Error::BadAuth()),
+            _ => parse_answer(&resp.text().await?),
+        };
+        let message = match answer {
+            Ok(message) => message,
+            Err(err) => {
+                match err {
+                    Dyndns2Error::BadAuth()
+                    | Dyndns2Error::Abuse()
+                    | Dyndns2Error::BadAgent()
+                    | Dyndns2Error::NotDonator() => {
+                        *self.stopped.lock().unwrap() = Some(err.clone());
+                    }
+                    Dyndns2Error::NoHost() | Dyndns2Error::NotFqdn() => {
+                        self.stopped_hosts
+                            .lock()
+                            .unwrap()
+                            .insert(hostname, err.clone());
+                    }
+                    Dyndns2Error::ServerError(_) => {
+                        *self.paused.lock().unwrap() =
+                            Some((Instant::now() + SERVER_ERROR_DELAY, err.clone()));
+                    }
+                    Dyndns2Error::Unknown(_) => {}
+                }
+                return Err(err.into());
+            }
+        };
+        self.sent
+            .lock()
+            .unwrap()
+            .insert((hostname, rrset_type.to_string()), rrset.clone());
+        Ok(Published {
+            message,
+            cause: None,
+        })
+    }
+    async fn delete_rrset(
+        &self,
+        _zone: &str,
+        _name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        // There is no way to remove a host with the protocol
+        Err(ClientError::UnsupportedType(
+            "dyndns2".to_string(),
+            rrset_type.to_string(),
+        ))
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+
+    use std::time::Duration;
+
+    use super::{parse_answer, Dyndns2};
+    use crate::{
+        config::Dyndns2Config,
+        provider::{DnsProvider, Rrset},
+        ClientError, Dyndns2Error,
+    };
+
+    fn provider(server: &MockServer) -> Dyndns2 {
+        Dyndns2::new(
+            &Dyndns2Config {
+                url: server.url("/nic/update"),
+                username: "user".to_string(),
+                password: "pass".to_string(),
+                password_file: None,
+                password_env: None,
+            },
+            Duration::ZERO,
+        )
+        .expect("Failed to create provider")
+    }
+
+    fn rrset(value: &str) -> Rrset {
+        Rrset {
+            values: vec![value.to_string()],
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn parse_answer_test() {
+        assert_eq!(
+            parse_answer("good 93.184.216.34\n").unwrap(),
+            "good 93.184.216.34"
+        );
+        assert_eq!(
+            parse_answer("nochg 93.184.216.34").unwrap(),
+            "nochg 93.184.216.34"
+        );
+        assert!(matches!(
+            parse_answer("badauth"),
+            Err(Dyndns2Error::BadAuth())
+        ));
+        assert!(matches!(parse_answer("abuse"), Err(Dyndns2Error::Abuse())));
+        assert!(matches!(
+            parse_answer("nohost"),
+            Err(Dyndns2Error::NoHost())
+        ));
+        assert!(matches!(
+            parse_answer("911"),
+            Err(Dyndns2Error::ServerError(_))
+        ));
+        assert!(matches!(
+            parse_answer("<html>"),
+            Err(Dyndns2Error::Unknown(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn update_test() {
+        let server = MockServer::start();
+        let update = server.mock(|when, then| {
+            when.method("GET")
+                .path("/nic/update")
+                .query_param("hostname", "home.example.com")
+                .query_param("myip", "93.184.216.34")
+                // "user:pass" in base64
+                .header("Authorization", "Basic dXNlcjpwYXNz");
+            then.status(200).body("good 93.184.216.34");
+        });
+
+        let provider = provider(&server);
+        assert_eq!(
+            provider
+                .get_rrset("example.com", "home", "A")
+                .await
+                .expect("Failed to get records"),
+            None
+        );
+        let published = provider
+            .set_rrset("example.com", "home", "A", &rrset("93.184.216.34"))
+            .await
+            .expect("Failed to update");
+        assert_eq!(published.message, "good 93.184.216.34");
+        update.assert();
+        // Remembers what it sent, so it isn't sent again
+        assert_eq!(
+            provider
+                .get_rrset("example.com", "home", "A")
+                .await
+                .expect("Failed to get records"),
+            Some(rrset("93.184.216.34"))
+        );
+    }
+
+    #[tokio::test]
+    async fn abuse_test() {
+        let server = MockServer::start();
+        let update = server.mock(|when, then| {
+            when.method("GET").path("/nic/update");
+            then.status(200).body("abuse");
+        });
+
+        let provider = provider(&server);
+        for _ in 0..2 {
+            let result = provider
+                .set_rrset("example.com", "home", "A", &rrset("93.184.216.34"))
+                .await;
+            assert!(matches!(
+                result,
+                Err(ClientError::Dyndns2(Dyndns2Error::Abuse()))
+            ));
+        }
+        // Stops sending updates once the service said to
+        update.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn nohost_test() {
+        let server = MockServer::start();
+        let nohost = server.mock(|when, then| {
+            when.method("GET")
+                .path("/nic/update")
+                .query_param("hostname", "home.example.com");
+            then.status(200).body("nohost");
+        });
+        let office = server.mock(|when, then| {
+            when.method("GET")
+                .path("/nic/update")
+                .query_param("hostname", "office.example.com");
+            then.status(200).body("good 93.184.216.34");
+        });
+
+        let provider = provider(&server);
+        for _ in 0..2 {
+            let result = provider
+                .set_rrset("example.com", "home", "A", &rrset("93.184.216.34"))
+                .await;
+            assert!(matches!(
+                result,
+                Err(ClientError::Dyndns2(Dyndns2Error::NoHost()))
+            ));
+        }
+        // Only that host is stopped
+        provider
+            .set_rrset("example.com", "office", "A", &rrset("93.184.216.34"))
+            .await
+            .expect("Failed to update");
+        nohost.assert_hits(1);
+        office.assert();
+    }
+
+    #[tokio::test]
+    async fn server_error_test() {
+        let server = MockServer::start();
+        let update = server.mock(|when, then| {
+            when.method("GET").path("/nic/update");
+            then.status(200).body("911");
+        });
+
+        let provider = provider(&server);
+        for name in ["home", "office"] {
+            let result = provider
+                .set_rrset("example.com", name, "A", &rrset("93.184.216.34"))
+                .await;
+            assert!(matches!(
+                result,
+                Err(ClientError::Dyndns2(Dyndns2Error::ServerError(_)))
+            ));
+        }
+        // Waits before trying again
+        update.assert_hits(1);
+    }
+}
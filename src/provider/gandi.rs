@@ -0,0 +1,322 @@
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{header, Client, ClientBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::{send, DnsProvider, Published, Rrset};
+use crate::config::{AuthType, GandiConfig};
+use crate::{ApiError, ClientError};
+
+/// 30 requests per minute, see https://api.gandi.net/docs/reference/
+const GANDI_RATE_LIMIT: u32 = 30;
+
+pub(crate) fn api_client(api_key: &str, auth_type: AuthType) -> Result<Client, ClientError> {
+    let client_builder = ClientBuilder::new();
+
+    let key = match auth_type {
+        AuthType::ApiKey => format!("Apikey {api_key}"),
+        AuthType::Pat => format!("Bearer {api_key}"),
+    };
+    let mut auth_value = header::HeaderValue::from_str(&key)?;
+    let mut headers = header::HeaderMap::new();
+    auth_value.set_sensitive(true);
+    headers.insert(header::AUTHORIZATION, auth_value);
+    let accept_value = header::HeaderValue::from_static("application/json");
+    headers.insert(header::ACCEPT, accept_value);
+    let client = client_builder.default_headers(headers).build()?;
+    Ok(client)
+}
+
+pub(crate) struct GandiAPI<'t> {
+    pub(crate) base_url: &'t str,
+    pub(crate) fqdn: &'t str,
+    pub(crate) rrset_name: &'t str,
+    pub(crate) rrset_type: &'t str,
+}
+
+impl<'t> GandiAPI<'t> {
+    pub(crate) fn url(&self) -> String {
+        format!(
+            "{}/v5/livedns/domains/{}/records/{}/{}",
+            self.base_url, self.fqdn, self.rrset_name, self.rrset_type
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct APIPayload {
+    pub rrset_values: Vec<String>,
+    pub rrset_ttl: u32,
+}
+
+impl From<APIPayload> for Rrset {
+    fn from(payload: APIPayload) -> Rrset {
+        Rrset {
+            values: payload.rrset_values,
+            ttl: payload.rrset_ttl,
+        }
+    }
+}
+
+impl From<&Rrset> for APIPayload {
+    fn from(rrset: &Rrset) -> APIPayload {
+        APIPayload {
+            rrset_values: rrset.values.clone(),
+            rrset_ttl: rrset.ttl,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+// Allowing dead code because this is the API response we get from Gandi.
+// We don't necessarily need all the fields, but we get them anyway.
+#[allow(dead_code)]
+pub(crate) struct ApiResponse {
+    pub(crate) message: String,
+    pub(crate) cause: Option<String>,
+    code: Option<i32>,
+    object: Option<String>,
+}
+
+async fn api_error(resp: Response, auth_type: AuthType) -> Result<ApiError, ClientError> {
+    Ok(match (resp.status(), auth_type) {
+        (StatusCode::UNAUTHORIZED, AuthType::ApiKey) => ApiError::Unauthorized(),
+        (StatusCode::UNAUTHORIZED, AuthType::Pat) => ApiError::TokenRejected(),
+        (StatusCode::TOO_MANY_REQUESTS, _) => ApiError::RateLimited(),
+        (StatusCode::FORBIDDEN, AuthType::ApiKey) => {
+            let body: ApiResponse = resp.json().await?;
+            ApiError::Forbidden {
+                message: body.message,
+            }
+        }
+        (StatusCode::FORBIDDEN, AuthType::Pat) => {
+            let body: ApiResponse = resp.json().await?;
+            ApiError::TokenMissingScope {
+                message: body.message,
+            }
+        }
+        (status, _) => {
+            // Errors from proxies in front of the API may not be JSON
+            let text = resp.text().await?;
+            let message = serde_json::from_str::<ApiResponse>(&text)
+                .map(|body| body.message)
+                .unwrap_or(text);
+            ApiError::Unknown(status, message)
+        }
+    })
+}
+
+#[derive(Clone)]
+pub(crate) struct GandiClient {
+    pub(crate) client: Client,
+    pub(crate) auth_type: AuthType,
+    /// How long to keep retrying requests that failed because the API was
+    /// overloaded or couldn't be reached.
+    pub(crate) retry_budget: Duration,
+}
+
+impl GandiClient {
+    /// Gets the current records, or `None` if there are none yet.
+    pub(crate) async fn get_rrset(
+        &self,
+        url: &str,
+    ) -> Result<Result<Option<APIPayload>, ApiError>, ClientError> {
+        let resp = send(self.client.get(url), self.retry_budget).await?;
+        Ok(match resp.status() {
+            StatusCode::OK => Ok(Some(resp.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(api_error(resp, self.auth_type).await?),
+        })
+    }
+
+    /// Replaces the records, and returns the response from the API.
+    pub(crate) async fn put_rrset(
+        &self,
+        url: &str,
+        payload: &APIPayload,
+    ) -> Result<Result<ApiResponse, ApiError>, ClientError> {
+        let resp = send(self.client.put(url).json(payload), self.retry_budget).await?;
+        Ok(match resp.status() {
+            StatusCode::CREATED => Ok(resp.json().await?),
+            _ => Err(api_error(resp, self.auth_type).await?),
+        })
+    }
+
+    /// Removes the records. Records that are already gone are not an error.
+    pub(crate) async fn delete_rrset(
+        &self,
+        url: &str,
+    ) -> Result<Result<(), ApiError>, ClientError> {
+        let resp = send(self.client.delete(url), self.retry_budget).await?;
+        Ok(match resp.status() {
+            StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => Ok(()),
+            _ => Err(api_error(resp, self.auth_type).await?),
+        })
+    }
+}
+
+/// Gandi LiveDNS, see https://api.gandi.net/docs/livedns/
+pub(crate) struct Gandi {
+    client: GandiClient,
+    base_url: String,
+}
+
+impl Gandi {
+    pub(crate) fn new(config: &GandiConfig, retry_budget: Duration) -> Result<Gandi, ClientError> {
+        Ok(Gandi {
+            client: GandiClient {
+                client: api_client(&config.api_key, config.auth_type)?,
+                auth_type: config.auth_type,
+                retry_budget,
+            },
+            base_url: config.api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self, zone: &str, name: &str, rrset_type: &str) -> String {
+        GandiAPI {
+            base_url: &self.base_url,
+            fqdn: zone,
+            rrset_name: name,
+            rrset_type,
+        }
+        .url()
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Gandi {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        let url = self.url(zone, name, rrset_type);
+        Ok(self.client.get_rrset(&url).await??.map(Rrset::from))
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        let url = self.url(zone, name, rrset_type);
+        let body = self.client.put_rrset(&url, &rrset.into()).await??;
+        Ok(Published {
+            message: body.message,
+            cause: body.cause.filter(|cause| !cause.is_empty()),
+        })
+    }
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        let url = self.url(zone, name, rrset_type);
+        Ok(self.client.delete_rrset(&url).await??)
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        NonZeroU32::new(GANDI_RATE_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use reqwest::Client;
+
+    use std::time::{Duration, Instant};
+
+    use super::{APIPayload, GandiClient};
+    use crate::{config::AuthType, ApiError};
+
+    #[tokio::test]
+    async fn token_errors_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("PUT").path("/expired");
+            then.status(401).body(
+                "{\"cause\":\"Unauthorized\", \"code\":401, \"message\":\"\", \"object\":\"HTTPUnauthorized\"}",
+            );
+        });
+        server.mock(|when, then| {
+            when.method("PUT").path("/scope");
+            then.status(403).body(
+                "{\"cause\":\"Forbidden\", \"code\":403, \"message\":\"Access was denied to this resource.\", \"object\":\"HTTPForbidden\"}",
+            );
+        });
+        let payload = APIPayload {
+            rrset_values: vec!["93.184.216.34".to_string()],
+            rrset_ttl: 300,
+        };
+        let client = |auth_type| GandiClient {
+            client: Client::new(),
+            auth_type,
+            retry_budget: Duration::ZERO,
+        };
+
+        let expired = client(AuthType::Pat)
+            .put_rrset(&server.url("/expired"), &payload)
+            .await
+            .expect("Request failed");
+        assert!(matches!(expired, Err(ApiError::TokenRejected())));
+        let scope = client(AuthType::Pat)
+            .put_rrset(&server.url("/scope"), &payload)
+            .await
+            .expect("Request failed");
+        assert!(matches!(scope, Err(ApiError::TokenMissingScope { .. })));
+        let api_key = client(AuthType::ApiKey)
+            .put_rrset(&server.url("/expired"), &payload)
+            .await
+            .expect("Request failed");
+        assert!(matches!(api_key, Err(ApiError::Unauthorized())));
+    }
+
+    #[tokio::test]
+    async fn retry_test() {
+        let server = MockServer::start();
+        let limited = server.mock(|when, then| {
+            when.method("GET").path("/limited");
+            then.status(429).header("Retry-After", "1").body(
+                "{\"cause\":\"Too Many Requests\", \"code\":429, \"message\":\"\", \"object\":\"\"}",
+            );
+        });
+        let unavailable = server.mock(|when, then| {
+            when.method("GET").path("/unavailable");
+            then.status(503).body("<html>Service Unavailable</html>");
+        });
+        let client = GandiClient {
+            client: Client::new(),
+            auth_type: AuthType::ApiKey,
+            retry_budget: Duration::from_secs(3),
+        };
+
+        // Retries when the server asked, until the budget runs out
+        let started = Instant::now();
+        let result = client
+            .get_rrset(&server.url("/limited"))
+            .await
+            .expect("Request failed");
+        assert!(matches!(result, Err(ApiError::RateLimited())));
+        let hits = limited.hits();
+        assert!(hits >= 2, "Only tried {hits} times");
+        // Waited at least as long as the server asked between attempts
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(hits as u64 - 1),
+            "{hits} attempts in {elapsed:?}"
+        );
+
+        let result = client
+            .get_rrset(&server.url("/unavailable"))
+            .await
+            .expect("Request failed");
+        assert!(matches!(result, Err(ApiError::Unknown(_, _))));
+        assert!(unavailable.hits() > 1);
+    }
+}
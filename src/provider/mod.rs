@@ -0,0 +1,362 @@
+//! The DNS services that records can be published to. The updater only talks
+//! to them through `DnsProvider`, so each entry can use a different one.
+
+pub(crate) mod cloudflare;
+pub(crate) mod dyndns2;
+pub(crate) mod gandi;
+pub(crate) mod powerdns;
+pub(crate) mod rfc2136;
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use die_exit::Die;
+use governor::clock::DefaultClock;
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{Jitter, Quota, RateLimiter};
+use reqwest::{header::RETRY_AFTER, Method, RequestBuilder, Response, StatusCode};
+use tokio::time::sleep;
+
+use crate::config::{Config, ProviderConfig, DEFAULT_PROVIDER};
+use crate::{retry::Backoff, ClientError};
+use cloudflare::Cloudflare;
+use dyndns2::Dyndns2;
+use gandi::Gandi;
+use powerdns::PowerDns;
+use rfc2136::Rfc2136;
+
+/// If we hit the rate limit, wait up to this many seconds before next attempt
+const DELAY_JITTER: u64 = 20;
+
+/// The records of one name and type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rrset {
+    pub values: Vec<String>,
+    pub ttl: u32,
+}
+
+impl Rrset {
+    /// Whether the records are the same, ignoring the order of the values and
+    /// how the addresses are written.
+    pub(crate) fn same_as(&self, other: &Rrset) -> bool {
+        fn normalized(values: &[String]) -> Vec<String> {
+            let mut values: Vec<String> = values
+                .iter()
+                .map(|value| {
+                    value
+                        .parse::<IpAddr>()
+                        .map(|ip| ip.to_string())
+                        .unwrap_or_else(|_| value.clone())
+                })
+                .collect();
+            values.sort();
+            values
+        }
+        self.ttl == other.ttl && normalized(&self.values) == normalized(&other.values)
+    }
+
+    /// Describes how the records would change from `current` to these.
+    pub(crate) fn describe_change(&self, current: Option<&Rrset>) -> String {
+        let values = self.values.join(", ");
+        match current {
+            None => format!("would be created with {values} (TTL {})", self.ttl),
+            Some(current) => {
+                let mut changes = Vec::new();
+                let current_values = current.values.join(", ");
+                if current_values != values {
+                    changes.push(format!("{current_values} -> {values}"));
+                }
+                if current.ttl != self.ttl {
+                    changes.push(format!("TTL {} -> {}", current.ttl, self.ttl));
+                }
+                format!("would change {}", changes.join(", "))
+            }
+        }
+    }
+}
+
+/// What the provider said after changing the records.
+#[derive(Debug)]
+pub(crate) struct Published {
+    pub(crate) message: String,
+    pub(crate) cause: Option<String>,
+}
+
+/// The full name of a record in the zone.
+pub(crate) fn owner(zone: &str, name: &str) -> String {
+    let zone = zone.trim_end_matches('.');
+    match name {
+        "@" => zone.to_string(),
+        name => format!("{name}.{zone}"),
+    }
+}
+
+/// A name in the data of a record, which is relative to the zone unless it
+/// ends with a dot.
+pub(crate) fn absolute(zone: &str, name: &str) -> String {
+    match name.strip_suffix('.') {
+        Some(name) => name.to_string(),
+        None => owner(zone, name),
+    }
+}
+
+/// The opposite of `absolute`, names in the zone are written relative to it
+/// like they usually are in the config.
+pub(crate) fn relative(zone: &str, name: &str) -> String {
+    let zone = zone.trim_end_matches('.');
+    // Compared as bytes, names from providers may not be ASCII and the offset
+    // could be inside a character. A match starts with the dot, so slicing at
+    // it is fine.
+    match name
+        .len()
+        .checked_sub(zone.len() + 1)
+        .filter(|&end| name.as_bytes()[end..].eq_ignore_ascii_case(format!(".{zone}").as_bytes()))
+    {
+        Some(end) if end > 0 => name[..end].to_string(),
+        _ => format!("{name}."),
+    }
+}
+
+/// A DNS service that hosts the records of some domains. The zone is the
+/// domain as configured, and the name is relative to it, `@` for the domain
+/// itself.
+#[async_trait]
+pub(crate) trait DnsProvider: Send + Sync {
+    /// Gets the current records, or `None` if there are none yet.
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError>;
+    /// Replaces the records with these.
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError>;
+    /// Removes the records. Records that are already gone are not an error.
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError>;
+    /// How many requests per minute the provider allows, if it has a limit.
+    fn rate_limit(&self) -> Option<NonZeroU32>;
+}
+
+/// Waits until the rate limit of the provider allows another request before
+/// passing each one on.
+struct RateLimited {
+    provider: Box<dyn DnsProvider>,
+    governor: Option<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
+}
+
+impl RateLimited {
+    fn new(provider: Box<dyn DnsProvider>) -> RateLimited {
+        RateLimited {
+            governor: provider
+                .rate_limit()
+                .map(|limit| RateLimiter::direct(Quota::per_minute(limit))),
+            provider,
+        }
+    }
+
+    async fn ready(&self) {
+        if let Some(governor) = &self.governor {
+            governor
+                .until_ready_with_jitter(Jitter::new(
+                    Duration::ZERO,
+                    Duration::from_secs(DELAY_JITTER),
+                ))
+                .await;
+        }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for RateLimited {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        self.ready().await;
+        self.provider.get_rrset(zone, name, rrset_type).await
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        self.ready().await;
+        self.provider.set_rrset(zone, name, rrset_type, rrset).await
+    }
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        self.ready().await;
+        self.provider.delete_rrset(zone, name, rrset_type).await
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        self.provider.rate_limit()
+    }
+}
+
+fn build(
+    name: &str,
+    config: &ProviderConfig,
+    retry_budget: Duration,
+) -> Result<Box<dyn DnsProvider>, ClientError> {
+    Ok(match config {
+        ProviderConfig::Gandi(gandi) => Box::new(Gandi::new(gandi, retry_budget)?),
+        ProviderConfig::Rfc2136(rfc2136) => Box::new(Rfc2136::new(name, rfc2136, retry_budget)?),
+        ProviderConfig::Cloudflare(cloudflare) => {
+            Box::new(Cloudflare::new(cloudflare, retry_budget)?)
+        }
+        ProviderConfig::Dyndns2(dyndns2) => Box::new(Dyndns2::new(dyndns2, retry_budget)?),
+        ProviderConfig::PowerDns(powerdns) => Box::new(PowerDns::new(powerdns, retry_budget)?),
+    })
+}
+
+/// The providers in the config, by name.
+pub(crate) struct Providers {
+    providers: BTreeMap<String, Arc<dyn DnsProvider>>,
+}
+
+impl Providers {
+    pub(crate) fn new(config: &Config) -> Result<Providers, ClientError> {
+        let retry_budget = Duration::from_secs(config.retry_budget);
+        let mut providers: BTreeMap<String, Arc<dyn DnsProvider>> = BTreeMap::new();
+        providers.insert(
+            DEFAULT_PROVIDER.to_string(),
+            Arc::new(RateLimited::new(Box::new(Gandi::new(
+                &config.gandi,
+                retry_budget,
+            )?))),
+        );
+        for (name, provider) in &config.providers {
+            providers.insert(
+                name.clone(),
+                Arc::new(RateLimited::new(build(name, provider, retry_budget)?)),
+            );
+        }
+        Ok(Providers { providers })
+    }
+
+    /// The provider with this name. The names entries use are checked when
+    /// validating the config, so this is only missing for a bad config.
+    pub(crate) fn get(&self, name: &str) -> Arc<dyn DnsProvider> {
+        self.providers
+            .get(name)
+            .die("The provider is missing, the config should have been validated")
+            .clone()
+    }
+}
+
+/// How long the server asked us to wait, if it did. Only the number of seconds
+/// form is supported, which is what the providers send.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let seconds = resp.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Sends the request, retrying on rate limits, server errors, and connection
+/// failures while the retry budget allows. A POST creates something each time
+/// it goes through, so it is only retried when the server certainly didn't act
+/// on it: it was rate limited, or the connection couldn't be made at all.
+pub(crate) async fn send(
+    request: RequestBuilder,
+    retry_budget: Duration,
+) -> Result<Response, ClientError> {
+    let idempotent = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .is_some_and(|request| request.method() != Method::POST);
+    let mut backoff = Backoff::new(retry_budget);
+    loop {
+        let attempt = request
+            .try_clone()
+            .die("Requests to the API should be possible to retry");
+        let (result, wait) = match attempt.send().await {
+            Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                let wait = retry_after(&resp);
+                (Ok(resp), wait)
+            }
+            Ok(resp) if resp.status().is_server_error() && idempotent => {
+                let wait = retry_after(&resp);
+                (Ok(resp), wait)
+            }
+            Err(err) if err.is_connect() || (err.is_timeout() && idempotent) => (Err(err), None),
+            result => return Ok(result?),
+        };
+        match backoff.next_delay(wait) {
+            Some(delay) => sleep(delay).await,
+            None => return Ok(result?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relative, Rrset};
+
+    fn rrset(values: &[&str], ttl: u32) -> Rrset {
+        Rrset {
+            values: values.iter().map(|v| v.to_string()).collect(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn same_as_test() {
+        assert!(rrset(&["93.184.216.34"], 300).same_as(&rrset(&["93.184.216.34"], 300)));
+        assert!(rrset(&["2606:4700:0:0::1111"], 300).same_as(&rrset(&["2606:4700::1111"], 300)));
+        assert!(rrset(&["1.1.1.1", "1.0.0.1"], 300).same_as(&rrset(&["1.0.0.1", "1.1.1.1"], 300)));
+        assert!(!rrset(&["93.184.216.34"], 300).same_as(&rrset(&["93.184.216.34"], 600)));
+        assert!(!rrset(&["93.184.216.34"], 300).same_as(&rrset(&["93.184.216.35"], 300)));
+    }
+
+    #[test]
+    fn describe_change_test() {
+        let wanted = rrset(&["93.184.216.34"], 300);
+        assert_eq!(
+            wanted.describe_change(None),
+            "would be created with 93.184.216.34 (TTL 300)"
+        );
+        assert_eq!(
+            wanted.describe_change(Some(&rrset(&["93.184.216.35"], 600))),
+            "would change 93.184.216.35 -> 93.184.216.34, TTL 600 -> 300"
+        );
+        assert_eq!(
+            wanted.describe_change(Some(&rrset(&["93.184.216.34"], 600))),
+            "would change TTL 600 -> 300"
+        );
+    }
+
+    #[test]
+    fn relative_test() {
+        assert_eq!(relative("example.com", "www.example.com"), "www");
+        assert_eq!(relative("example.com.", "WWW.Example.COM"), "WWW");
+        assert_eq!(
+            relative("example.com", "mail.example.org"),
+            "mail.example.org."
+        );
+        // The offset would be inside the "é"
+        assert_eq!(relative("example.com", "é.example.co"), "é.example.co.");
+        assert_eq!(relative("example.com", "café.example.com"), "café");
+    }
+}
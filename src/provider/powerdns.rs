@@ -0,0 +1,375 @@
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{header, Client, ClientBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::{absolute, owner, relative, send, DnsProvider, Published, Rrset};
+use crate::config::PowerDnsConfig;
+use crate::ClientError;
+
+fn api_client(api_key: &str) -> Result<Client, ClientError> {
+    let mut key_value = header::HeaderValue::from_str(api_key)?;
+    key_value.set_sensitive(true);
+    let mut headers = header::HeaderMap::new();
+    headers.insert("X-API-Key", key_value);
+    Ok(ClientBuilder::new().default_headers(headers).build()?)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct RrsetResponse {
+    name: String,
+    #[serde(rename = "type")]
+    rrset_type: String,
+    ttl: u32,
+    records: Vec<Record>,
+}
+
+#[derive(Deserialize)]
+struct ZoneResponse {
+    #[serde(default)]
+    rrsets: Vec<RrsetResponse>,
+}
+
+#[derive(Serialize)]
+struct RrsetChange<'c> {
+    name: &'c str,
+    #[serde(rename = "type")]
+    rrset_type: &'c str,
+    changetype: &'c str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    records: Vec<Record>,
+}
+
+#[derive(Serialize)]
+struct ZonePatch<'p> {
+    rrsets: Vec<RrsetChange<'p>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The name with the trailing dot, which is how the API writes all names.
+fn canonical(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Turns a value written the way it is in the config into the content of a
+/// record, where names are always absolute.
+fn to_content(zone: &str, rrset_type: &str, value: &str) -> String {
+    match rrset_type {
+        "CNAME" => canonical(&absolute(zone, value)),
+        "MX" => match value.split_once(' ') {
+            Some((priority, host)) => {
+                format!("{priority} {}", canonical(&absolute(zone, host.trim())))
+            }
+            // Validated with the config, leave it for the server to reject
+            None => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+/// The opposite of `to_content`, so the records can be compared with the
+/// values we want.
+fn from_content(zone: &str, rrset_type: &str, content: &str) -> String {
+    match rrset_type {
+        "CNAME" => relative(zone, content.trim_end_matches('.')),
+        "MX" => match content.split_once(' ') {
+            Some((priority, host)) => {
+                format!("{priority} {}", relative(zone, host.trim_end_matches('.')))
+            }
+            None => content.to_string(),
+        },
+        _ => content.to_string(),
+    }
+}
+
+/// The authoritative server of PowerDNS, see
+/// https://doc.powerdns.com/authoritative/http-api/
+pub(crate) struct PowerDns {
+    client: Client,
+    base_url: String,
+    rate_limit: Option<NonZeroU32>,
+    /// How long to keep retrying requests that failed because the API was
+    /// overloaded or couldn't be reached.
+    retry_budget: Duration,
+}
+
+impl PowerDns {
+    pub(crate) fn new(
+        config: &PowerDnsConfig,
+        retry_budget: Duration,
+    ) -> Result<PowerDns, ClientError> {
+        Ok(PowerDns {
+            client: api_client(&config.api_key)?,
+            base_url: format!(
+                "{}/api/v1/servers/{}",
+                config.api_url.trim_end_matches('/'),
+                config.server_id
+            ),
+            rate_limit: config.rate_limit,
+            retry_budget,
+        })
+    }
+
+    fn zone_url(&self, zone: &str) -> String {
+        format!("{}/zones/{}", self.base_url, canonical(zone))
+    }
+
+    async fn api_error(zone: &str, resp: Response) -> Result<ClientError, ClientError> {
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(ClientError::ZoneNotFound(zone.to_string()));
+        }
+        // Errors from proxies in front of the API may not be JSON
+        let text = resp.text().await?;
+        let message = serde_json::from_str::<ErrorResponse>(&text)
+            .map(|body| body.error)
+            .unwrap_or(text);
+        Ok(ClientError::PowerDns(status, message))
+    }
+
+    /// Sends a change to the RRsets of the zone.
+    async fn patch(&self, zone: &str, change: RrsetChange<'_>) -> Result<(), ClientError> {
+        let patch = ZonePatch {
+            rrsets: vec![change],
+        };
+        let request = self.client.patch(self.zone_url(zone)).json(&patch);
+        let resp = send(request, self.retry_budget).await?;
+        match resp.status() {
+            StatusCode::NO_CONTENT | StatusCode::OK => Ok(()),
+            _ => Err(Self::api_error(zone, resp).await?),
+        }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for PowerDns {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        let owner = canonical(&owner(zone, name));
+        // Older servers ignore the filters and send the whole zone, so the
+        // RRset is picked out here too
+        let request = self.client.get(self.zone_url(zone)).query(&[
+            ("rrsets", "true"),
+            ("rrset_name", owner.as_str()),
+            ("rrset_type", rrset_type),
+        ]);
+        let resp = send(request, self.retry_budget).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(Self::api_error(zone, resp).await?);
+        }
+        let body: ZoneResponse = resp.json().await?;
+        let Some(rrset) = body.rrsets.into_iter().find(|rrset| {
+            rrset.name.eq_ignore_ascii_case(&owner) && rrset.rrset_type == rrset_type
+        }) else {
+            return Ok(None);
+        };
+        let values: Vec<String> = rrset
+            .records
+            .iter()
+            .filter(|record| !record.disabled)
+            .map(|record| from_content(zone, rrset_type, &record.content))
+            .collect();
+        if values.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Rrset {
+            values,
+            ttl: rrset.ttl,
+        }))
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        let owner = canonical(&owner(zone, name));
+        self.patch(
+            zone,
+            RrsetChange {
+                name: &owner,
+                rrset_type,
+                changetype: "REPLACE",
+                ttl: Some(rrset.ttl),
+                records: rrset
+                    .values
+                    .iter()
+                    .map(|value| Record {
+                        content: to_content(zone, rrset_type, value),
+                        disabled: false,
+                    })
+                    .collect(),
+            },
+        )
+        .await?;
+        Ok(Published {
+            message: "DNS record updated".to_string(),
+            cause: None,
+        })
+    }
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        let owner = canonical(&owner(zone, name));
+        self.patch(
+            zone,
+            RrsetChange {
+                name: &owner,
+                rrset_type,
+                changetype: "DELETE",
+                ttl: None,
+                records: Vec::new(),
+            },
+        )
+        .await
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        self.rate_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+
+    use std::time::Duration;
+
+    use super::PowerDns;
+    use crate::{
+        config::PowerDnsConfig,
+        provider::{DnsProvider, Rrset},
+        ClientError,
+    };
+
+    fn provider(server: &MockServer) -> PowerDns {
+        PowerDns::new(
+            &PowerDnsConfig {
+                api_url: server.base_url(),
+                api_key: "kkk".to_string(),
+                api_key_file: None,
+                api_key_env: None,
+                server_id: "localhost".to_string(),
+                rate_limit: None,
+            },
+            Duration::ZERO,
+        )
+        .expect("Failed to create provider")
+    }
+
+    #[tokio::test]
+    async fn get_rrset_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/api/v1/servers/localhost/zones/example.com.")
+                .header("X-API-Key", "kkk");
+            then.status(200).body(
+                "{\"name\":\"example.com.\",\"rrsets\":[{\"name\":\"example.com.\",\"type\":\"MX\",\"ttl\":300,\"records\":[{\"content\":\"10 mail.example.com.\",\"disabled\":false}]},{\"name\":\"www.example.com.\",\"type\":\"A\",\"ttl\":600,\"records\":[{\"content\":\"93.184.216.34\",\"disabled\":false},{\"content\":\"93.184.216.35\",\"disabled\":true}]}]}",
+            );
+        });
+
+        let provider = provider(&server);
+        assert_eq!(
+            provider
+                .get_rrset("example.com", "www", "A")
+                .await
+                .expect("Failed to get records"),
+            Some(Rrset {
+                values: vec!["93.184.216.34".to_string()],
+                ttl: 600,
+            })
+        );
+        assert_eq!(
+            provider
+                .get_rrset("example.com", "@", "MX")
+                .await
+                .expect("Failed to get records"),
+            Some(Rrset {
+                values: vec!["10 mail".to_string()],
+                ttl: 300,
+            })
+        );
+        assert_eq!(
+            provider
+                .get_rrset("example.com", "www", "AAAA")
+                .await
+                .expect("Failed to get records"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn set_rrset_test() {
+        let server = MockServer::start();
+        let patch = server.mock(|when, then| {
+            when.method("PATCH")
+                .path("/api/v1/servers/localhost/zones/example.com.")
+                .header("X-API-Key", "kkk")
+                .json_body_partial("{\"rrsets\":[{\"name\":\"www.example.com.\",\"type\":\"CNAME\",\"changetype\":\"REPLACE\",\"ttl\":300,\"records\":[{\"content\":\"other.example.com.\",\"disabled\":false}]}]}");
+            then.status(204);
+        });
+
+        let rrset = Rrset {
+            values: vec!["other".to_string()],
+            ttl: 300,
+        };
+        provider(&server)
+            .set_rrset("example.com", "www", "CNAME", &rrset)
+            .await
+            .expect("Failed to set records");
+        patch.assert();
+    }
+
+    #[tokio::test]
+    async fn errors_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/api/v1/servers/localhost/zones/example.org.");
+            then.status(404)
+                .body("{\"error\":\"Could not find domain 'example.org.'\"}");
+        });
+        server.mock(|when, then| {
+            when.method("PATCH")
+                .path("/api/v1/servers/localhost/zones/example.com.");
+            then.status(422).body(
+                "{\"error\":\"RRset www.example.com. IN A: Conflicts with pre-existing RRset\"}",
+            );
+        });
+
+        let provider = provider(&server);
+        let result = provider.get_rrset("example.org", "www", "A").await;
+        assert!(matches!(result, Err(ClientError::ZoneNotFound(_))));
+        let result = provider.delete_rrset("example.com", "www", "A").await;
+        match result {
+            Err(ClientError::PowerDns(status, message)) => {
+                assert_eq!(status.as_u16(), 422);
+                assert!(message.starts_with("RRset www.example.com."));
+            }
+            other => panic!("Expected a PowerDNS error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Dynamic updates sent straight to the primary server of a zone, like BIND
+//! or Knot, see RFC 2136. The updates are signed with a TSIG key.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::net::lookup_host;
+use tokio::time::sleep;
+
+use super::{absolute, owner, relative, DnsProvider, Published, Rrset};
+use crate::config::{ConfigError, Rfc2136Config};
+use crate::dns::{
+    self, encode_name, tsig::TsigKey, txt_strings, type_code, DnsError, Message, Reader, Record,
+    CLASS_ANY, CLASS_IN, RCODE_NXDOMAIN, TYPE_A, TYPE_AAAA, TYPE_CAA, TYPE_CNAME, TYPE_MX,
+    TYPE_TXT,
+};
+use crate::{retry::Backoff, ClientError};
+
+pub(crate) struct Rfc2136 {
+    server: String,
+    port: u16,
+    key: TsigKey,
+    /// How long to keep trying when the server doesn't respond.
+    retry_budget: Duration,
+}
+
+/// Splits the strings of a TXT value, each of which may be quoted.
+fn txt_parse(value: &str) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            break;
+        };
+        let mut string = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('\\') => string.push(chars.next().ok_or("ends with a backslash")?),
+                    Some('"') => break,
+                    Some(c) => string.push(c),
+                    None => return Err("a quote is not closed".to_string()),
+                }
+            }
+        } else {
+            string.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                string.push(c);
+            }
+        }
+        if string.len() > 255 {
+            return Err("a string is longer than 255 bytes".to_string());
+        }
+        strings.push(string);
+    }
+    Ok(strings)
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Turns a value, written the way it is in the config, into record data.
+fn to_rdata(zone: &str, rtype: u16, value: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    match rtype {
+        TYPE_A => {
+            let ip: Ipv4Addr = value.parse().map_err(|_| "not an IPv4 address")?;
+            data.extend_from_slice(&ip.octets());
+        }
+        TYPE_AAAA => {
+            let ip: Ipv6Addr = value.parse().map_err(|_| "not an IPv6 address")?;
+            data.extend_from_slice(&ip.octets());
+        }
+        TYPE_CNAME => encode_name(&mut data, &absolute(zone, value)),
+        TYPE_MX => {
+            let (priority, host) = value
+                .split_once(' ')
+                .ok_or("expected a priority and a host")?;
+            let priority: u16 = priority
+                .parse()
+                .map_err(|_| "the priority is not a number")?;
+            data.extend_from_slice(&priority.to_be_bytes());
+            encode_name(&mut data, &absolute(zone, host.trim()));
+        }
+        TYPE_TXT => {
+            for string in txt_parse(value)? {
+                data.push(string.len() as u8);
+                data.extend_from_slice(string.as_bytes());
+            }
+        }
+        TYPE_CAA => {
+            let mut parts = value.splitn(3, ' ');
+            let (Some(flags), Some(tag), Some(property)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err("expected flags, a tag and a value".to_string());
+            };
+            let flags: u8 = flags.parse().map_err(|_| "the flags are not a number")?;
+            let property = txt_parse(property)?.concat();
+            data.push(flags);
+            data.push(tag.len() as u8);
+            data.extend_from_slice(tag.as_bytes());
+            data.extend_from_slice(property.as_bytes());
+        }
+        _ => return Err("the type is not supported".to_string()),
+    }
+    Ok(data)
+}
+
+/// Turns record data into a value written the way it is in the config, so it
+/// can be compared with the values we want.
+fn from_rdata(zone: &str, rtype: u16, data: &[u8]) -> Result<String, DnsError> {
+    let malformed = || DnsError::Malformed(format!("record data of type {rtype} is not valid"));
+    Ok(match rtype {
+        TYPE_A => {
+            let octets: [u8; 4] = data.try_into().map_err(|_| malformed())?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        TYPE_AAAA => {
+            let octets: [u8; 16] = data.try_into().map_err(|_| malformed())?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        TYPE_CNAME => relative(zone, &Reader::new(data).name()?),
+        TYPE_MX => {
+            let mut reader = Reader::new(data);
+            let priority = reader.u16()?;
+            format!("{priority} {}", relative(zone, &reader.name()?))
+        }
+        TYPE_TXT => txt_strings(data)?
+            .iter()
+            .map(|string| quote(string))
+            .collect::<Vec<_>>()
+            .join(" "),
+        TYPE_CAA => {
+            let mut reader = Reader::new(data);
+            let flags = reader.u8()?;
+            let tag_len = reader.u8()? as usize;
+            let tag = String::from_utf8_lossy(reader.bytes(tag_len)?).to_string();
+            let property = String::from_utf8_lossy(&data[2 + tag_len..]).to_string();
+            format!("{flags} {tag} {}", quote(&property))
+        }
+        _ => return Err(malformed()),
+    })
+}
+
+impl Rfc2136 {
+    pub(crate) fn new(
+        name: &str,
+        config: &Rfc2136Config,
+        retry_budget: Duration,
+    ) -> Result<Rfc2136, ClientError> {
+        let secret = STANDARD
+            .decode(&config.key_secret)
+            .map_err(|err| ConfigError::InvalidSecret(name.to_string(), err.to_string()))?;
+        Ok(Rfc2136 {
+            server: config.server.clone(),
+            port: config.port,
+            key: TsigKey::new(&config.key_name, &secret),
+            retry_budget,
+        })
+    }
+
+    async fn server(&self) -> Result<SocketAddr, ClientError> {
+        let lookup_error = |reason: String| {
+            ClientError::ServerLookup(self.server.clone(), "IP".to_string(), reason)
+        };
+        lookup_host((self.server.as_str(), self.port))
+            .await
+            .map_err(|err| lookup_error(err.to_string()))?
+            .next()
+            .ok_or_else(|| lookup_error("no addresses found".to_string()))
+    }
+
+    /// Sends the message, and tries again while the retry budget allows if
+    /// the server doesn't respond. Resending is safe, because the updates
+    /// replace whole RRsets.
+    async fn exchange(&self, message: &Message) -> Result<Message, ClientError> {
+        let server = self.server().await?;
+        let mut backoff = Backoff::new(self.retry_budget);
+        loop {
+            match dns::exchange_signed(server, message, &self.key).await {
+                Err(DnsError::Timeout()) => match backoff.next_delay(None) {
+                    Some(delay) => sleep(delay).await,
+                    None => return Err(DnsError::Timeout().into()),
+                },
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    async fn update(&self, zone: &str, updates: Vec<Record>) -> Result<(), ClientError> {
+        let message = Message::update(rand::random(), zone.trim_end_matches('.'), updates);
+        self.exchange(&message).await?;
+        Ok(())
+    }
+}
+
+fn rrset_type_code(rrset_type: &str) -> Result<u16, ClientError> {
+    type_code(rrset_type).ok_or_else(|| ClientError::BadEntry(rrset_type.to_string()))
+}
+
+/// Removes the whole RRset, when sent in the update section.
+fn delete_all(owner: &str, rtype: u16) -> Record {
+    Record {
+        name: owner.to_string(),
+        rtype,
+        class: CLASS_ANY,
+        ttl: 0,
+        data: Vec::new(),
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    async fn get_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<Option<Rrset>, ClientError> {
+        let rtype = rrset_type_code(rrset_type)?;
+        let owner = owner(zone, name);
+        let query = Message::query(rand::random(), &owner, rtype);
+        let response = match self.exchange(&query).await {
+            Err(ClientError::Dns(DnsError::Rcode(RCODE_NXDOMAIN))) => return Ok(None),
+            result => result?,
+        };
+        let records: Vec<&Record> = response
+            .answers
+            .iter()
+            .filter(|record| record.rtype == rtype && record.name.eq_ignore_ascii_case(&owner))
+            .collect();
+        let Some(first) = records.first() else {
+            return Ok(None);
+        };
+        Ok(Some(Rrset {
+            values: records
+                .iter()
+                .map(|record| from_rdata(zone, rtype, &record.data))
+                .collect::<Result<_, _>>()?,
+            ttl: first.ttl,
+        }))
+    }
+    async fn set_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+        rrset: &Rrset,
+    ) -> Result<Published, ClientError> {
+        let rtype = rrset_type_code(rrset_type)?;
+        let owner = owner(zone, name);
+        // Replace the RRset in one update, so it's never half changed
+        let mut updates = vec![delete_all(&owner, rtype)];
+        for value in &rrset.values {
+            updates.push(Record {
+                name: owner.clone(),
+                rtype,
+                class: CLASS_IN,
+                ttl: rrset.ttl,
+                data: to_rdata(zone, rtype, value).map_err(|reason| {
+                    ClientError::Rdata(rrset_type.to_string(), value.clone(), reason)
+                })?,
+            });
+        }
+        self.update(zone, updates).await?;
+        Ok(Published {
+            message: "DNS record updated".to_string(),
+            cause: None,
+        })
+    }
+    async fn delete_rrset(
+        &self,
+        zone: &str,
+        name: &str,
+        rrset_type: &str,
+    ) -> Result<(), ClientError> {
+        let rtype = rrset_type_code(rrset_type)?;
+        self.update(zone, vec![delete_all(&owner(zone, name), rtype)])
+            .await
+    }
+    fn rate_limit(&self) -> Option<NonZeroU32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::{from_rdata, to_rdata, Rfc2136};
+    use crate::{
+        config::Rfc2136Config,
+        dns::{
+            tsig::{self, TsigKey},
+            Message, Record, CLASS_ANY, CLASS_IN, TYPE_A, TYPE_CAA, TYPE_CNAME, TYPE_MX, TYPE_TSIG,
+            TYPE_TXT,
+        },
+        provider::{DnsProvider, Rrset},
+        ClientError,
+    };
+
+    const KEY_NAME: &str = "update-key";
+    /// "not a very secret secret" in base64.
+    const SECRET: &str = "bm90IGEgdmVyeSBzZWNyZXQgc2VjcmV0";
+
+    #[test]
+    fn rdata_test() {
+        let values = [
+            (TYPE_A, "93.184.216.34"),
+            (TYPE_CNAME, "other"),
+            (TYPE_CNAME, "example.net."),
+            (TYPE_MX, "10 mail"),
+            (TYPE_TXT, "\"v=spf1 -all\""),
+            (TYPE_TXT, "\"first\" \"say \\\"hi\\\"\""),
+            (TYPE_CAA, "0 issue \"letsencrypt.org\""),
+        ];
+        for (rtype, value) in values {
+            let data = to_rdata("example.com", rtype, value).expect("Failed to encode value");
+            assert_eq!(
+                from_rdata("example.com", rtype, &data).expect("Failed to decode value"),
+                value
+            );
+        }
+        assert!(to_rdata("example.com", TYPE_A, "2606:4700::1111").is_err());
+        assert!(to_rdata("example.com", TYPE_TXT, "\"not closed").is_err());
+    }
+
+    /// Starts a DNS server that checks the signature of every message, keeps
+    /// the updates it gets, and answers queries with these records.
+    async fn stub_server(answers: Vec<Record>, rcode: u16) -> (u16, Arc<Mutex<Vec<Message>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind stub server");
+        let port = socket.local_addr().unwrap().port();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let received = updates.clone();
+        let key = TsigKey::new(KEY_NAME, b"not a very secret secret");
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let mut request = Message::decode(&buf[..len]).unwrap();
+                let signature = request.additional.pop().expect("Request is not signed");
+                assert_eq!(signature.rtype, TYPE_TSIG);
+                // Signing the request again with the same time has to give
+                // the same MAC, the algorithm name takes up the first 13 bytes
+                let mut time = [0u8; 8];
+                time[2..].copy_from_slice(&signature.data[13..19]);
+                let (signed, mac) = key.sign(&request, u64::from_be_bytes(time));
+                assert_eq!(signed, buf[..len], "Request signature doesn't match");
+
+                let mut response = Message {
+                    flags: 0x8400 | (request.flags & 0x7800) | rcode,
+                    ..request.clone()
+                };
+                if request.flags & 0x7800 == 0 {
+                    response.answers = answers.clone();
+                } else {
+                    received.lock().unwrap().push(request);
+                }
+                let bytes = key.sign_response(&response, &mac, tsig::now());
+                socket.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        (port, updates)
+    }
+
+    fn provider(port: u16, secret: &str) -> Rfc2136 {
+        Rfc2136::new(
+            "home",
+            &Rfc2136Config {
+                server: "127.0.0.1".to_string(),
+                port,
+                key_name: KEY_NAME.to_string(),
+                key_secret: secret.to_string(),
+                key_secret_file: None,
+            },
+            Duration::ZERO,
+        )
+        .expect("Failed to create provider")
+    }
+
+    #[tokio::test]
+    async fn set_rrset_test() {
+        let (port, updates) = stub_server(vec![], 0).await;
+        let rrset = Rrset {
+            values: vec!["93.184.216.34".to_string(), "93.184.216.35".to_string()],
+            ttl: 300,
+        };
+        provider(port, SECRET)
+            .set_rrset("example.com", "www", "A", &rrset)
+            .await
+            .expect("Failed to update");
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].questions[0].name, "example.com");
+        let changes = &updates[0].authority;
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].name, "www.example.com");
+        assert_eq!((changes[0].class, changes[0].ttl), (CLASS_ANY, 0));
+        assert!(changes[0].data.is_empty());
+        assert_eq!((changes[1].class, changes[1].ttl), (CLASS_IN, 300));
+        assert_eq!(changes[1].data, vec![93, 184, 216, 34]);
+        assert_eq!(changes[2].data, vec![93, 184, 216, 35]);
+    }
+
+    #[tokio::test]
+    async fn get_rrset_test() {
+        let (port, _) = stub_server(
+            vec![Record {
+                name: "www.example.com".to_string(),
+                rtype: TYPE_A,
+                class: CLASS_IN,
+                ttl: 600,
+                data: vec![93, 184, 216, 34],
+            }],
+            0,
+        )
+        .await;
+        let rrset = provider(port, SECRET)
+            .get_rrset("example.com", "www", "A")
+            .await
+            .expect("Failed to query");
+        assert_eq!(
+            rrset,
+            Some(Rrset {
+                values: vec!["93.184.216.34".to_string()],
+                ttl: 600,
+            })
+        );
+
+        let (port, _) = stub_server(vec![], 3).await;
+        let rrset = provider(port, SECRET)
+            .get_rrset("example.com", "missing", "A")
+            .await
+            .expect("Failed to query");
+        assert_eq!(rrset, None);
+    }
+
+    #[tokio::test]
+    async fn refused_test() {
+        let (port, _) = stub_server(vec![], 5).await;
+        let result = provider(port, SECRET)
+            .delete_rrset("example.com", "www", "A")
+            .await;
+        assert!(
+            matches!(result, Err(ClientError::Dns(_))),
+            "Expected an error, got {result:?}"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Validation of the record types with values given in the config, rather
+//! than addresses that are found out at runtime.
+
+/// The record types that can be set to static values.
+pub const STATIC_TYPES: &[&str] = &["TXT", "CNAME", "MX", "CAA"];
+
+/// The longest string a TXT record can hold, see RFC 1035 section 3.3.14.
+const MAX_TXT_LEN: usize = 255;
+
+/// Checks if the name is a valid host name, either relative to the domain or
+/// fully qualified with a trailing dot.
+fn is_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+fn is_quoted(value: &str) -> bool {
+    value.len() >= 2 && value.starts_with('"') && value.ends_with('"')
+}
+
+/// Checks a value of a static record, returning what's wrong with it if it's
+/// not valid for the type.
+pub fn validate_value(record_type: &str, value: &str) -> Result<(), String> {
+    match record_type {
+        "TXT" => {
+            // Already quoted values are passed on as they are, they may hold
+            // multiple strings to get around the length limit.
+            if !is_quoted(value) && value.len() > MAX_TXT_LEN {
+                return Err(format!(
+                    "longer than {MAX_TXT_LEN} bytes, split it into multiple quoted strings"
+                ));
+            }
+            Ok(())
+        }
+        "CNAME" => {
+            if !is_hostname(value) {
+                return Err("not a valid host name".to_string());
+            }
+            Ok(())
+        }
+        "MX" => {
+            let (priority, host) = value
+                .split_once(' ')
+                .ok_or_else(|| "expected a priority and a host, like '10 mail'".to_string())?;
+            priority.parse::<u16>().map_err(|_| {
+                format!("priority '{priority}' is not a number between 0 and 65535")
+            })?;
+            if !is_hostname(host.trim()) {
+                return Err(format!("'{}' is not a valid host name", host.trim()));
+            }
+            Ok(())
+        }
+        "CAA" => {
+            let mut parts = value.splitn(3, ' ');
+            let (flags, tag, property) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(flags), Some(tag), Some(property)) => (flags, tag, property),
+                _ => {
+                    return Err(
+                        "expected flags, a tag and a value, like '0 issue \"letsencrypt.org\"'"
+                            .to_string(),
+                    )
+                }
+            };
+            flags
+                .parse::<u8>()
+                .map_err(|_| format!("flags '{flags}' is not a number between 0 and 255"))?;
+            if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("tag '{tag}' can only contain letters and numbers"));
+            }
+            if !is_quoted(property.trim()) {
+                return Err(format!("value {property} must be in double quotes"));
+            }
+            Ok(())
+        }
+        _ => Err(format!("type {record_type} can't have static values")),
+    }
+}
+
+/// Formats a value the way the Gandi API expects it.
+pub fn api_value(record_type: &str, value: &str) -> String {
+    match record_type {
+        "TXT" if !is_quoted(value) => {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{api_value, validate_value};
+
+    #[test]
+    fn txt_test() {
+        assert!(validate_value("TXT", "v=spf1 -all").is_ok());
+        assert!(validate_value("TXT", &"a".repeat(256)).is_err());
+        assert!(validate_value("TXT", &format!("\"{}\" \"b\"", "a".repeat(255))).is_ok());
+        assert_eq!(api_value("TXT", "v=spf1 -all"), "\"v=spf1 -all\"");
+        assert_eq!(api_value("TXT", "say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(api_value("TXT", "\"quoted\""), "\"quoted\"");
+    }
+
+    #[test]
+    fn cname_test() {
+        assert!(validate_value("CNAME", "home").is_ok());
+        assert!(validate_value("CNAME", "home.example.com.").is_ok());
+        assert!(validate_value("CNAME", "not a host").is_err());
+        assert!(validate_value("CNAME", "-bad.example.com").is_err());
+        assert!(validate_value("CNAME", "").is_err());
+    }
+
+    #[test]
+    fn mx_test() {
+        assert!(validate_value("MX", "10 mail.example.com.").is_ok());
+        assert!(validate_value("MX", "mail.example.com.").is_err());
+        assert!(validate_value("MX", "100000 mail").is_err());
+        assert!(validate_value("MX", "10 not..valid").is_err());
+    }
+
+    #[test]
+    fn caa_test() {
+        assert!(validate_value("CAA", "0 issue \"letsencrypt.org\"").is_ok());
+        assert!(validate_value("CAA", "0 issue letsencrypt.org").is_err());
+        assert!(validate_value("CAA", "256 issue \"letsencrypt.org\"").is_err());
+        assert!(validate_value("CAA", "0 issue").is_err());
+    }
+}
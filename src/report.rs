@@ -0,0 +1,89 @@
+//! A summary of a run, for `--output json`.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub ipv4: IpReport,
+    pub ipv6: IpReport,
+    pub records: Vec<RecordReport>,
+    /// Whether every record was updated or already up to date.
+    pub success: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct IpReport {
+    pub address: Option<String>,
+    /// Which IP source found the address.
+    pub source: Option<String>,
+    pub error: Option<String>,
+}
+
+impl IpReport {
+    pub fn new<T: Display, E: Display>(result: Result<&(T, String), E>) -> IpReport {
+        match result {
+            Ok((address, source)) => IpReport {
+                address: Some(address.to_string()),
+                source: Some(source.clone()),
+                error: None,
+            },
+            Err(err) => IpReport {
+                error: Some(err.to_string()),
+                ..IpReport::default()
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    Updated,
+    UpToDate,
+    /// The record would be updated, if this wasn't a dry run.
+    WouldUpdate,
+    /// An earlier run already published this, so the provider wasn't asked.
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecordReport {
+    pub entry_name: String,
+    pub entry_type: String,
+    pub fqdn: String,
+    pub status: RecordStatus,
+    /// The message from the provider, or what went wrong.
+    pub message: Option<String>,
+    pub cause: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IpReport, RecordReport, RecordStatus, RunReport};
+
+    #[test]
+    fn serialize_test() {
+        let report = RunReport {
+            ipv4: IpReport::new(Ok::<_, String>(&("93.184.216.34", "Ipify".to_string()))),
+            ipv6: IpReport::new(Err::<&(String, String), _>("no address")),
+            records: vec![RecordReport {
+                entry_name: "@".to_string(),
+                entry_type: "A".to_string(),
+                fqdn: "example.com".to_string(),
+                status: RecordStatus::UpToDate,
+                message: None,
+                cause: None,
+            }],
+            success: true,
+        };
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["ipv4"]["address"], "93.184.216.34");
+        assert_eq!(json["ipv4"]["source"], "Ipify");
+        assert_eq!(json["ipv6"]["error"], "no address");
+        assert_eq!(json["records"][0]["status"], "up_to_date");
+        assert_eq!(json["success"], true);
+    }
+}
@@ -0,0 +1,128 @@
+//! Works out how long to wait between attempts when retrying requests that
+//! failed for reasons that may go away on their own.
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// The wait before the first retry, doubled after every attempt.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+/// The longest we wait between attempts, unless the server asks for longer.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub(crate) struct Backoff {
+    /// Retries stop once this much time has passed since the first attempt.
+    budget: Duration,
+    started: Instant,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(budget: Duration) -> Backoff {
+        Backoff {
+            budget,
+            started: Instant::now(),
+            attempt: 0,
+        }
+    }
+
+    /// Returns how long to wait before trying again, or `None` if waiting
+    /// would go over the budget. If the server said when to come back, we wait
+    /// at least that long, but never less than the exponential backoff so a
+    /// server asking for no wait at all doesn't get hammered.
+    pub(crate) fn next_delay(&mut self, retry_after: Option<Duration>) -> Option<Duration> {
+        let max = INITIAL_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(MAX_DELAY);
+        // Wait somewhere between half and all of it, so that requests that
+        // failed together don't all retry at the same moment
+        let backoff = max / 2 + rand::thread_rng().gen_range(Duration::ZERO..=max / 2);
+        self.attempt += 1;
+        let remaining = self.budget.saturating_sub(self.started.elapsed());
+        match retry_after {
+            Some(retry_after) => {
+                let delay = retry_after.max(backoff).max(INITIAL_DELAY);
+                if delay <= remaining {
+                    Some(delay)
+                } else if backoff.max(INITIAL_DELAY) <= remaining {
+                    // The server asked for longer than we can wait, it may
+                    // still have recovered by then
+                    Some(backoff.max(INITIAL_DELAY))
+                } else {
+                    None
+                }
+            }
+            None if backoff <= remaining => Some(backoff),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Backoff, INITIAL_DELAY, MAX_DELAY};
+
+    #[test]
+    fn exponential_backoff_test() {
+        let mut backoff = Backoff::new(Duration::from_secs(3600));
+        let mut max = INITIAL_DELAY;
+        for _ in 0..10 {
+            let delay = backoff.next_delay(None).expect("Budget ran out");
+            assert!(
+                delay >= max / 2 && delay <= max,
+                "{delay:?} not within {max:?}"
+            );
+            max = (max * 2).min(MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn retry_after_test() {
+        let mut backoff = Backoff::new(Duration::from_secs(60));
+        assert_eq!(
+            backoff.next_delay(Some(Duration::from_secs(42))),
+            Some(Duration::from_secs(42))
+        );
+        // Waiting this long would go over the budget, so the backoff is
+        // tried instead
+        let delay = backoff
+            .next_delay(Some(Duration::from_secs(120)))
+            .expect("Budget ran out");
+        assert!(
+            delay >= INITIAL_DELAY && delay <= INITIAL_DELAY * 2,
+            "{delay:?} is not the backoff"
+        );
+    }
+
+    #[test]
+    fn retry_after_zero_test() {
+        let mut backoff = Backoff::new(Duration::from_secs(3600));
+        let mut max = INITIAL_DELAY;
+        for _ in 0..5 {
+            // Still backs off when the server says not to wait
+            let delay = backoff
+                .next_delay(Some(Duration::ZERO))
+                .expect("Budget ran out");
+            assert!(
+                delay >= (max / 2).max(INITIAL_DELAY) && delay <= max,
+                "{delay:?} not within {max:?}"
+            );
+            max = (max * 2).min(MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn retry_after_over_budget_test() {
+        // Not even the backoff fits
+        let mut backoff = Backoff::new(Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(Some(Duration::from_secs(120))), None);
+    }
+
+    #[test]
+    fn no_budget_test() {
+        let mut backoff = Backoff::new(Duration::ZERO);
+        assert_eq!(backoff.next_delay(None), None);
+    }
+}
@@ -0,0 +1,681 @@
+//! A server for the dyndns2 protocol, so that routers which can only push
+//! their address to a DynDNS service can update records through us. See
+//! https://help.dyn.com/remote-access-api/perform-update/ for the protocol.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::header::{ALLOW, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use reqwest::Url;
+use ring::digest::{digest, SHA256};
+
+use crate::config::{Config, ConfigError};
+use crate::ip_source::common::{is_public_ipv4, is_public_ipv6};
+use crate::provider::{DnsProvider, Providers, Rrset};
+use crate::ClientError;
+
+/// Where clients send their updates.
+const UPDATE_PATH: &str = "/nic/update";
+/// Proxies add the address they got the request from to this header.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+/// How long an address or username is refused after wrong credentials,
+/// doubled with every failure in a row so passwords can't be guessed quickly.
+const FAILURE_DELAY: Duration = Duration::from_secs(1);
+const MAX_FAILURE_DELAY: Duration = Duration::from_secs(15 * 60);
+/// Failures that stopped this long ago are forgotten.
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// The answers to an update, one for each host.
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    /// The records were changed to these addresses.
+    Good(Vec<IpAddr>),
+    /// The records already had these addresses.
+    NoChange(Vec<IpAddr>),
+    /// The credentials are wrong for this host.
+    BadAuth,
+    /// Wrong credentials came from the same address or for the same username
+    /// recently, so these weren't checked. Clients see the same as for
+    /// `BadAuth`.
+    LockedOut,
+    /// The host is not one we serve, or belongs to another user.
+    NoHost,
+    /// No host was given, or it's not a full name.
+    NotFqdn,
+    /// The request didn't make sense, like a `myip` that's not an address.
+    BadAgent,
+    /// The update failed on our side, clients should try again later.
+    ServerError,
+}
+
+fn join_ips(ips: &[IpAddr]) -> String {
+    ips.iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Answer {
+    fn code(&self) -> String {
+        match self {
+            Answer::Good(addresses) => format!("good {}", join_ips(addresses)),
+            Answer::NoChange(addresses) => format!("nochg {}", join_ips(addresses)),
+            Answer::BadAuth | Answer::LockedOut => "badauth".to_string(),
+            Answer::NoHost => "nohost".to_string(),
+            Answer::NotFqdn => "notfqdn".to_string(),
+            Answer::BadAgent => "badagent".to_string(),
+            Answer::ServerError => "911".to_string(),
+        }
+    }
+}
+
+/// A host that clients may update, with where its records are.
+struct Host {
+    username: String,
+    password: String,
+    zone: String,
+    rrset_name: String,
+    ttl: u32,
+    provider: Arc<dyn DnsProvider>,
+}
+
+/// What wrong credentials count against. Counting the username too means
+/// guessing from many addresses doesn't help either.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum FailureKey {
+    Address(IpAddr),
+    Username(String),
+}
+
+/// Wrong credentials sent from an address, or for a username.
+struct Failures {
+    /// How many in a row.
+    count: u32,
+    /// Requests are refused until then.
+    until: Instant,
+}
+
+/// Turns dyndns2 updates into changes to the records.
+pub(crate) struct Gateway {
+    /// The hosts by their full name, in lowercase.
+    hosts: BTreeMap<String, Host>,
+    /// Proxies whose `X-Forwarded-For` tells where requests came from.
+    trusted_proxies: Vec<IpAddr>,
+    /// The addresses and usernames that recently had wrong credentials.
+    failures: Mutex<BTreeMap<FailureKey, Failures>>,
+}
+
+/// Compares the digests, so the time it takes doesn't tell how much of the
+/// password was right.
+fn same_secret(given: &str, expected: &str) -> bool {
+    digest(&SHA256, given.as_bytes()).as_ref() == digest(&SHA256, expected.as_bytes()).as_ref()
+}
+
+/// The username and password from a basic authorization header.
+fn basic_auth(request: &Request<Body>) -> Option<(String, String)> {
+    let value = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Turns IPv4 addresses written as IPv6 back into IPv4, the peers of a socket
+/// listening on both families look like that.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// The address of the client. Requests through a trusted proxy came from
+/// the last address it and any proxies before it forwarded for, others are
+/// taken from where the connection came from since anyone can send the
+/// header.
+fn client_address(request: &Request<Body>, peer: IpAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = canonical(peer);
+    if !trusted_proxies.contains(&client) {
+        return client;
+    }
+    let forwarded: Vec<&str> = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    for hop in forwarded.into_iter().rev() {
+        // Beyond an address that doesn't parse, nothing can be trusted
+        let Ok(ip) = hop.parse().map(canonical) else {
+            break;
+        };
+        client = ip;
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+    }
+    client
+}
+
+/// The addresses to publish, from `myip` or the address the request came
+/// from. Clients with both families send them separated by commas.
+fn addresses(myip: Option<&str>, remote: IpAddr) -> Option<Vec<IpAddr>> {
+    let addresses = match myip.filter(|myip| !myip.is_empty()) {
+        Some(myip) => myip
+            .split(',')
+            .map(|ip| ip.trim().parse().ok())
+            .collect::<Option<Vec<IpAddr>>>()?,
+        None => vec![remote],
+    };
+    let public = addresses.iter().all(|ip| match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    });
+    public.then_some(addresses)
+}
+
+impl Gateway {
+    pub(crate) fn new(conf: &Config) -> Result<Gateway, ClientError> {
+        let serve = conf
+            .serve
+            .as_ref()
+            .filter(|serve| !serve.hosts.is_empty())
+            .ok_or(ConfigError::NoServeHosts())?;
+        let providers = Providers::new(conf)?;
+        let mut hosts = BTreeMap::new();
+        for host in &serve.hosts {
+            let (zone, rrset_name) = Config::find_zone(conf, &host.hostname)
+                .ok_or_else(|| ConfigError::UnknownServeHost(host.hostname.clone()))?;
+            hosts.insert(
+                host.hostname.trim_end_matches('.').to_ascii_lowercase(),
+                Host {
+                    username: host.username.clone(),
+                    password: host.password.clone(),
+                    zone: zone.to_string(),
+                    rrset_name,
+                    ttl: host.ttl.unwrap_or(conf.ttl),
+                    provider: providers.get(Config::zone_provider(conf, zone)),
+                },
+            );
+        }
+        Ok(Gateway {
+            hosts,
+            trusted_proxies: serve
+                .trusted_proxies
+                .iter()
+                .copied()
+                .map(canonical)
+                .collect(),
+            failures: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Whether requests from the address or for the username are refused
+    /// for now, because of wrong credentials earlier.
+    fn locked_out(&self, keys: &[FailureKey]) -> bool {
+        let failures = self.failures.lock().unwrap();
+        keys.iter().any(|key| {
+            failures
+                .get(key)
+                .is_some_and(|failures| Instant::now() < failures.until)
+        })
+    }
+
+    /// Refuses the address and username for a while, longer each time they
+    /// fail again.
+    fn failed(&self, keys: &[FailureKey]) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, failures| now < failures.until + FAILURE_MEMORY);
+        for key in keys {
+            let count = failures.get(key).map_or(0, |failures| failures.count) + 1;
+            let delay = FAILURE_DELAY
+                .saturating_mul(2u32.saturating_pow(count - 1))
+                .min(MAX_FAILURE_DELAY);
+            failures.insert(
+                key.clone(),
+                Failures {
+                    count,
+                    until: now + delay,
+                },
+            );
+        }
+    }
+
+    /// Whether the credentials belong to any of the hosts.
+    fn authenticate(&self, (username, password): &(String, String)) -> bool {
+        self.hosts
+            .values()
+            .any(|host| username == &host.username && same_secret(password, &host.password))
+    }
+
+    /// Publishes the addresses for a host, if it belongs to the credentials.
+    async fn update(
+        &self,
+        hostname: &str,
+        (username, password): &(String, String),
+        addresses: &[IpAddr],
+    ) -> Answer {
+        // Hosts of other users don't exist as far as this one is concerned
+        let Some(host) = self
+            .hosts
+            .get(&hostname.trim_end_matches('.').to_ascii_lowercase())
+            .filter(|host| username == &host.username)
+        else {
+            return Answer::NoHost;
+        };
+        if !same_secret(password, &host.password) {
+            return Answer::BadAuth;
+        }
+        let mut changed = false;
+        for (rrset_type, family) in [("A", true), ("AAAA", false)] {
+            let values: Vec<String> = addresses
+                .iter()
+                .filter(|ip| ip.is_ipv4() == family)
+                .map(|ip| ip.to_string())
+                .collect();
+            if values.is_empty() {
+                continue;
+            }
+            let wanted = Rrset {
+                values,
+                ttl: host.ttl,
+            };
+            match self.publish(host, rrset_type, &wanted).await {
+                Ok(published) => changed |= published,
+                Err(err) => {
+                    eprintln!("Failed to update {rrset_type} record of {hostname}: {err}");
+                    return Answer::ServerError;
+                }
+            }
+        }
+        if changed {
+            println!("Updated {hostname} to {}", join_ips(addresses));
+            Answer::Good(addresses.to_vec())
+        } else {
+            Answer::NoChange(addresses.to_vec())
+        }
+    }
+
+    /// Returns whether the records had to be changed.
+    async fn publish(
+        &self,
+        host: &Host,
+        rrset_type: &str,
+        wanted: &Rrset,
+    ) -> Result<bool, ClientError> {
+        let current = host
+            .provider
+            .get_rrset(&host.zone, &host.rrset_name, rrset_type)
+            .await?;
+        if current.is_some_and(|current| current.same_as(wanted)) {
+            return Ok(false);
+        }
+        host.provider
+            .set_rrset(&host.zone, &host.rrset_name, rrset_type, wanted)
+            .await?;
+        Ok(true)
+    }
+
+    pub(crate) async fn handle(&self, request: Request<Body>, peer: IpAddr) -> Response<Body> {
+        if request.uri().path() != UPDATE_PATH {
+            return respond(StatusCode::NOT_FOUND, "Not found");
+        }
+        if request.method() != Method::GET {
+            let mut response = respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+            response.headers_mut().insert(ALLOW, "GET".parse().unwrap());
+            return response;
+        }
+        let Some(credentials) = basic_auth(&request) else {
+            let mut response = respond(StatusCode::UNAUTHORIZED, &Answer::BadAuth.code());
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                "Basic realm=\"gandi-live-dns\"".parse().unwrap(),
+            );
+            return response;
+        };
+        // Nothing about the hosts is answered before the credentials are
+        // checked, and they aren't even checked while locked out, so guessing
+        // faster doesn't help
+        let remote = client_address(&request, peer, &self.trusted_proxies);
+        let keys = [
+            FailureKey::Address(remote),
+            FailureKey::Username(credentials.0.clone()),
+        ];
+        if self.locked_out(&keys) {
+            return respond(StatusCode::OK, &Answer::LockedOut.code());
+        }
+        if !self.authenticate(&credentials) {
+            self.failed(&keys);
+            return respond(StatusCode::OK, &Answer::BadAuth.code());
+        }
+        // The URI of a request only has the path and query
+        let query: BTreeMap<String, String> =
+            Url::parse(&format!("http://localhost{}", request.uri()))
+                .map(|url| url.query_pairs().into_owned().collect())
+                .unwrap_or_default();
+        let hostnames: Vec<&str> = query
+            .get("hostname")
+            .map(|hostnames| hostnames.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        if hostnames.is_empty() || hostnames.iter().any(|hostname| !hostname.contains('.')) {
+            return respond(StatusCode::OK, &Answer::NotFqdn.code());
+        }
+        let Some(addresses) = addresses(query.get("myip").map(String::as_str), remote) else {
+            return respond(StatusCode::OK, &Answer::BadAgent.code());
+        };
+        let mut answers = Vec::new();
+        for hostname in hostnames {
+            answers.push(self.update(hostname, &credentials, &addresses).await);
+        }
+        // Users with more than one host may still have guessed at another
+        if answers.contains(&Answer::BadAuth) {
+            self.failed(&keys);
+        } else {
+            let mut failures = self.failures.lock().unwrap();
+            for key in &keys {
+                failures.remove(key);
+            }
+        }
+        let answers: Vec<String> = answers.iter().map(Answer::code).collect();
+        respond(StatusCode::OK, &answers.join("\n"))
+    }
+}
+
+fn respond(status: StatusCode, body: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain".parse().unwrap());
+    response
+}
+
+/// Listens for updates until the process is stopped.
+pub(crate) async fn serve(conf: &Config, listen: Option<SocketAddr>) -> Result<(), ClientError> {
+    let gateway = Arc::new(Gateway::new(conf)?);
+    let listen = listen
+        .or_else(|| conf.serve.as_ref().map(|serve| serve.listen))
+        .ok_or(ConfigError::NoServeHosts())?;
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let gateway = gateway.clone();
+        let peer = conn.remote_addr().ip();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.handle(request, peer).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&listen)?.serve(make_service);
+    println!("Listening for dyndns2 updates on {listen}");
+    Ok(server.await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+    use hyper::{
+        body, header::AUTHORIZATION, http::request::Builder, Body, Method, Request, StatusCode,
+    };
+
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use super::{addresses, client_address, Gateway};
+    use crate::config::Config;
+
+    fn gateway(server: &MockServer) -> Gateway {
+        let mut conf: Config = toml::from_str(
+            r#"
+fqdn = "example.com"
+api_key = "xxx"
+
+[serve]
+trusted_proxies = ["127.0.0.1"]
+
+[[serve.host]]
+hostname = "home.example.com"
+username = "router"
+password = "hunter2"
+
+[[serve.host]]
+hostname = "nas.example.com"
+username = "nas"
+password = "hunter3"
+"#,
+        )
+        .expect("Failed to parse config");
+        conf.gandi.api_url = server.base_url();
+        Gateway::new(&conf).expect("Failed to create gateway")
+    }
+
+    /// Where the requests come from, unless a test says otherwise.
+    const CLIENT: &str = "93.184.216.40";
+
+    fn request(method: Method, query: &str, auth: Option<&str>) -> Builder {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("/nic/update?{query}"));
+        if let Some(auth) = auth {
+            request = request.header(AUTHORIZATION, format!("Basic {auth}"));
+        }
+        request
+    }
+
+    async fn send(gateway: &Gateway, request: Builder, peer: &str) -> (StatusCode, String) {
+        let response = gateway
+            .handle(request.body(Body::empty()).unwrap(), peer.parse().unwrap())
+            .await;
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    async fn update(gateway: &Gateway, query: &str, auth: Option<&str>) -> (StatusCode, String) {
+        send(gateway, request(Method::GET, query, auth), CLIENT).await
+    }
+
+    /// "router:hunter2" in base64.
+    const GOOD_AUTH: &str = "cm91dGVyOmh1bnRlcjI=";
+    /// "router:wrong" in base64.
+    const BAD_AUTH: &str = "cm91dGVyOndyb25n";
+    /// "nas:hunter3" in base64.
+    const NAS_AUTH: &str = "bmFzOmh1bnRlcjM=";
+
+    #[tokio::test]
+    async fn update_test() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method("GET")
+                .path("/v5/livedns/domains/example.com/records/home/A");
+            then.status(200)
+                .body("{\"rrset_values\":[\"93.184.216.34\"], \"rrset_ttl\":300}");
+        });
+        let put = server.mock(|when, then| {
+            when.method("PUT")
+                .path("/v5/livedns/domains/example.com/records/home/A")
+                .json_body_partial("{\"rrset_values\":[\"93.184.216.35\"]}");
+            then.status(201)
+                .body("{\"cause\":\"\", \"code\":201, \"message\":\"\", \"object\":\"\"}");
+        });
+        let gateway = gateway(&server);
+
+        assert_eq!(
+            update(
+                &gateway,
+                "hostname=home.example.com&myip=93.184.216.34",
+                Some(GOOD_AUTH)
+            )
+            .await,
+            (StatusCode::OK, "nochg 93.184.216.34".to_string())
+        );
+        assert_eq!(
+            update(
+                &gateway,
+                "hostname=home.example.com&myip=93.184.216.35",
+                Some(GOOD_AUTH)
+            )
+            .await,
+            (StatusCode::OK, "good 93.184.216.35".to_string())
+        );
+        put.assert();
+
+        // Without myip, the address the proxy got the request from is used
+        let (_, body) = send(
+            &gateway,
+            request(Method::GET, "hostname=home.example.com", Some(GOOD_AUTH))
+                .header("X-Forwarded-For", "93.184.216.34"),
+            "127.0.0.1",
+        )
+        .await;
+        assert_eq!(body, "nochg 93.184.216.34");
+    }
+
+    #[tokio::test]
+    async fn errors_test() {
+        let server = MockServer::start();
+        let gateway = gateway(&server);
+
+        let (status, body) = update(&gateway, "hostname=home.example.com", None).await;
+        assert_eq!(
+            (status, body.as_str()),
+            (StatusCode::UNAUTHORIZED, "badauth")
+        );
+        let (_, body) = update(&gateway, "hostname=other.example.com", Some(GOOD_AUTH)).await;
+        assert_eq!(body, "nohost");
+        // Hosts of other users don't exist either
+        let (_, body) = update(&gateway, "hostname=nas.example.com", Some(GOOD_AUTH)).await;
+        assert_eq!(body, "nohost");
+        let (_, body) = update(&gateway, "myip=93.184.216.34", Some(GOOD_AUTH)).await;
+        assert_eq!(body, "notfqdn");
+        let (_, body) = update(
+            &gateway,
+            "hostname=home.example.com&myip=192.168.1.1",
+            Some(GOOD_AUTH),
+        )
+        .await;
+        assert_eq!(body, "badagent");
+        // Wrong credentials don't find out which hosts exist
+        let (_, body) = update(&gateway, "hostname=other.example.com", Some(BAD_AUTH)).await;
+        assert_eq!(body, "badauth");
+    }
+
+    #[tokio::test]
+    async fn method_test() {
+        let server = MockServer::start();
+        let gateway = gateway(&server);
+
+        let (status, _) = send(
+            &gateway,
+            request(
+                Method::POST,
+                "hostname=home.example.com&myip=93.184.216.34",
+                Some(GOOD_AUTH),
+            ),
+            CLIENT,
+        )
+        .await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn lockout_test() {
+        let server = MockServer::start();
+        for name in ["home", "nas"] {
+            server.mock(|when, then| {
+                when.method("GET")
+                    .path(format!("/v5/livedns/domains/example.com/records/{name}/A"));
+                then.status(200)
+                    .body("{\"rrset_values\":[\"93.184.216.34\"], \"rrset_ttl\":300}");
+            });
+        }
+        let gateway = gateway(&server);
+        let home = "hostname=home.example.com&myip=93.184.216.34";
+        let nas = "hostname=nas.example.com&myip=93.184.216.34";
+        let other_client = "93.184.216.41";
+
+        let (_, body) = update(&gateway, home, Some(BAD_AUTH)).await;
+        assert_eq!(body, "badauth");
+        // Even the right password is refused for a while, from the same
+        // address and for the same username
+        let (_, body) = update(&gateway, nas, Some(NAS_AUTH)).await;
+        assert_eq!(body, "badauth");
+        let (_, body) = send(
+            &gateway,
+            request(Method::GET, home, Some(GOOD_AUTH)),
+            other_client,
+        )
+        .await;
+        assert_eq!(body, "badauth");
+        let (_, body) = send(
+            &gateway,
+            request(Method::GET, nas, Some(NAS_AUTH)),
+            other_client,
+        )
+        .await;
+        assert_eq!(body, "nochg 93.184.216.34");
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let (_, body) = update(&gateway, home, Some(GOOD_AUTH)).await;
+        assert_eq!(body, "nochg 93.184.216.34");
+    }
+
+    #[test]
+    fn client_address_test() {
+        let trusted: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let client = |peer: &str, forwarded: Option<&str>| {
+            let mut request = Request::builder();
+            if let Some(forwarded) = forwarded {
+                request = request.header("X-Forwarded-For", forwarded);
+            }
+            client_address(
+                &request.body(Body::empty()).unwrap(),
+                peer.parse().unwrap(),
+                &trusted,
+            )
+            .to_string()
+        };
+        // Anyone else can send the header
+        assert_eq!(client(CLIENT, Some("93.184.216.41")), CLIENT);
+        assert_eq!(client("127.0.0.1", None), "127.0.0.1");
+        // The client can send the header too, only what the proxies added
+        // counts
+        assert_eq!(
+            client("127.0.0.1", Some("203.0.113.9, 93.184.216.41, 10.0.0.2")),
+            "93.184.216.41"
+        );
+        assert_eq!(
+            client("::ffff:127.0.0.1", Some("93.184.216.41")),
+            "93.184.216.41"
+        );
+        assert_eq!(client("127.0.0.1", Some("garbage, 10.0.0.2")), "10.0.0.2");
+    }
+
+    #[test]
+    fn addresses_test() {
+        let remote: IpAddr = "93.184.216.40".parse().unwrap();
+        assert_eq!(addresses(None, remote), Some(vec![remote]));
+        assert_eq!(
+            addresses(Some("93.184.216.34,2606:4700::1111"), remote),
+            Some(vec![
+                "93.184.216.34".parse().unwrap(),
+                "2606:4700::1111".parse().unwrap()
+            ])
+        );
+        assert_eq!(addresses(Some("not an address"), remote), None);
+        assert_eq!(addresses(Some("10.0.0.1"), remote), None);
+    }
+}
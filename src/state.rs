@@ -0,0 +1,122 @@
+//! Remembers what was last published for each record, so that later runs can
+//! skip records that are already up to date without asking the API.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("Failed to access the state file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse the state file: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordState {
+    pub values: Vec<String>,
+    pub ttl: u32,
+    /// When the record was last published, in seconds since the Unix epoch.
+    pub updated: u64,
+}
+
+/// The records are keyed by the domain, then the record name, then the type.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    #[serde(default)]
+    records: BTreeMap<String, BTreeMap<String, BTreeMap<String, RecordState>>>,
+}
+
+impl State {
+    /// Loads the state, or starts with an empty one if the file doesn't exist
+    /// yet.
+    pub fn load(path: &Path) -> Result<State, StateError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the state to a temporary file and moves it into place, so the
+    /// file is never left half written.
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp = path.with_file_name(temp_name);
+        fs::write(&temp, serde_json::to_vec_pretty(self)?)?;
+        fs::File::open(&temp)?.sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, fqdn: &str, name: &str, rrset_type: &str) -> Option<&RecordState> {
+        self.records.get(fqdn)?.get(name)?.get(rrset_type)
+    }
+
+    /// Records that these values were just published.
+    pub fn set(&mut self, fqdn: &str, name: &str, rrset_type: &str, values: Vec<String>, ttl: u32) {
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+        self.records
+            .entry(fqdn.to_string())
+            .or_default()
+            .entry(name.to_string())
+            .or_default()
+            .insert(
+                rrset_type.to_string(),
+                RecordState {
+                    values,
+                    ttl,
+                    updated,
+                },
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use super::State;
+
+    #[test]
+    fn save_load_test() {
+        let dir = temp_dir().join("gandi-live-dns-test");
+        let path = dir.join("state-test.json");
+        let _ = fs::remove_file(&path);
+
+        let mut state = State::load(&path).expect("Missing state file should be empty");
+        assert!(state.get("example.com", "@", "A").is_none());
+
+        state.set(
+            "example.com",
+            "@",
+            "A",
+            vec!["93.184.216.34".to_string()],
+            300,
+        );
+        state.save(&path).expect("Failed to save state");
+        assert!(!dir.join("state-test.json.tmp").exists());
+
+        let loaded = State::load(&path).expect("Failed to load state");
+        let record = loaded
+            .get("example.com", "@", "A")
+            .expect("Record is missing from the state");
+        assert_eq!(record.values, vec!["93.184.216.34".to_string()]);
+        assert_eq!(record.ttl, 300);
+        assert!(record.updated > 0);
+        assert!(loaded.get("example.com", "@", "AAAA").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+//! Notices when the addresses of this machine change, by listening to the
+//! kernel over netlink, so updates don't have to wait for the next repeat.
+//! Only Linux has netlink, other platforms can keep using `--repeat`.
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use if_addrs::get_if_addrs;
+
+use crate::ClientError;
+
+/// How often to check anyway while watching, in seconds, when `--repeat`
+/// isn't given. Changes that don't show up on this machine, like a new
+/// address on the router in front of it, are only found by checking.
+pub(crate) const WATCH_POLL: u64 = 1800;
+/// How long to wait for things to settle after a change. Getting a new
+/// address usually comes with a burst of address and route events.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// The message types we listen for, see `linux/rtnetlink.h`.
+#[cfg(any(target_os = "linux", test))]
+const RTM_NEWADDR: u16 = 20;
+#[cfg(any(target_os = "linux", test))]
+const RTM_DELADDR: u16 = 21;
+#[cfg(any(target_os = "linux", test))]
+const RTM_NEWROUTE: u16 = 24;
+#[cfg(any(target_os = "linux", test))]
+const RTM_DELROUTE: u16 = 25;
+/// The size of `struct nlmsghdr`.
+#[cfg(any(target_os = "linux", test))]
+const HEADER_LEN: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+enum Event {
+    /// An address was added, removed, or had its lifetime refreshed.
+    Address,
+    /// A default route was added or removed, e.g. when switching uplinks.
+    DefaultRoute,
+    /// The kernel dropped events because we didn't read them fast enough,
+    /// so anything may have changed.
+    Overflow,
+}
+
+/// Finds the events in a batch of netlink messages. Each message is a
+/// `struct nlmsghdr` followed by the payload, aligned to 4 bytes.
+#[cfg(any(target_os = "linux", test))]
+fn parse_events(buf: &[u8]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while offset + HEADER_LEN <= buf.len() {
+        let len = u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        if len < HEADER_LEN || offset + len > buf.len() {
+            break;
+        }
+        let message_type = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+        match message_type {
+            RTM_NEWADDR | RTM_DELADDR => events.push(Event::Address),
+            // The payload starts with `struct rtmsg`, whose second byte is
+            // the prefix length of the destination, 0 for a default route
+            RTM_NEWROUTE | RTM_DELROUTE if buf.get(offset + HEADER_LEN + 1) == Some(&0) => {
+                events.push(Event::DefaultRoute)
+            }
+            _ => {}
+        }
+        offset += (len + 3) & !3;
+    }
+    events
+}
+
+/// The addresses of all interfaces, other than loopback.
+fn interface_addresses() -> BTreeSet<IpAddr> {
+    get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter(|interface| !interface.is_loopback())
+                .map(|interface| interface.ip())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::mem::{size_of, zeroed};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use tokio::io::unix::AsyncFd;
+
+    use super::{parse_events, Event};
+
+    /// A netlink socket that gets the address and route changes.
+    pub(super) struct Socket {
+        fd: AsyncFd<OwnedFd>,
+        buf: Vec<u8>,
+    }
+
+    impl Socket {
+        pub(super) fn open() -> io::Result<Socket> {
+            // SAFETY: takes no pointers, and the result is checked below
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the descriptor was just created, and nothing else owns it
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // SAFETY: sockaddr_nl is plain integers, for which all zeroes is
+            // valid, and zero is what the kernel expects in the unused fields
+            let mut address: libc::sockaddr_nl = unsafe { zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = (libc::RTMGRP_IPV4_IFADDR
+                | libc::RTMGRP_IPV6_IFADDR
+                | libc::RTMGRP_IPV4_ROUTE
+                | libc::RTMGRP_IPV6_ROUTE) as u32;
+            // SAFETY: the descriptor is open, and the pointer and length are
+            // those of the sockaddr_nl above, which outlives the call
+            let result = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Socket {
+                fd: AsyncFd::new(fd)?,
+                buf: vec![0; 64 * 1024],
+            })
+        }
+
+        /// Waits for the next batch of messages, and returns the events in it.
+        pub(super) async fn events(&mut self) -> io::Result<Vec<Event>> {
+            loop {
+                let mut guard = self.fd.readable().await?;
+                let buf = &mut self.buf;
+                let received = guard.try_io(|fd| {
+                    // SAFETY: the buffer is valid for writes of its length
+                    let len = unsafe {
+                        libc::recv(
+                            fd.as_raw_fd(),
+                            buf.as_mut_ptr() as *mut libc::c_void,
+                            buf.len(),
+                            0,
+                        )
+                    };
+                    if len < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(len as usize)
+                    }
+                });
+                match received {
+                    Ok(Ok(len)) => return Ok(parse_events(&self.buf[..len])),
+                    Ok(Err(err)) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                        return Ok(vec![Event::Overflow])
+                    }
+                    Ok(Err(err)) => return Err(err),
+                    // Not actually readable yet
+                    Err(_) => continue,
+                }
+            }
+        }
+    }
+}
+
+/// Waits for the addresses of this machine to change.
+pub(crate) struct Watcher {
+    #[cfg(target_os = "linux")]
+    socket: netlink::Socket,
+    /// The addresses when we last looked, to tell real changes apart from
+    /// lifetimes being refreshed.
+    addresses: BTreeSet<IpAddr>,
+}
+
+impl Watcher {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new() -> Result<Watcher, ClientError> {
+        Ok(Watcher {
+            socket: netlink::Socket::open().map_err(ClientError::Watch)?,
+            addresses: interface_addresses(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new() -> Result<Watcher, ClientError> {
+        Err(ClientError::Watch(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "watching for changes is only supported on Linux, use --repeat instead",
+        )))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn events(&mut self) -> Result<Vec<Event>, ClientError> {
+        self.socket.events().await.map_err(ClientError::Watch)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn events(&mut self) -> Result<Vec<Event>, ClientError> {
+        std::future::pending().await
+    }
+
+    /// Returns once an address or the default route changed, after waiting
+    /// for things to settle.
+    pub(crate) async fn changed(&mut self) -> Result<(), ClientError> {
+        loop {
+            let mut events = self.events().await?;
+            let deadline = tokio::time::Instant::now() + DEBOUNCE;
+            while let Ok(more) = tokio::time::timeout_at(deadline, self.events()).await {
+                events.extend(more?);
+            }
+            let addresses = interface_addresses();
+            let addresses_changed = addresses != self.addresses;
+            self.addresses = addresses;
+            if addresses_changed
+                || events
+                    .iter()
+                    .any(|event| matches!(event, Event::DefaultRoute | Event::Overflow))
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_events, Event, RTM_DELROUTE, RTM_NEWADDR, RTM_NEWROUTE};
+
+    /// Builds a netlink message with a payload of this many bytes, which
+    /// starts with the given bytes.
+    fn message(message_type: u16, payload: &[u8], len: usize) -> Vec<u8> {
+        let total = 16 + len;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(total as u32).to_ne_bytes());
+        buf.extend_from_slice(&message_type.to_ne_bytes());
+        buf.extend_from_slice(&[0; 10]);
+        buf.extend_from_slice(payload);
+        buf.resize(total, 0);
+        // Padded to 4 bytes, like the kernel does
+        buf.resize((total + 3) & !3, 0);
+        buf
+    }
+
+    #[test]
+    fn parse_events_test() {
+        let mut buf = message(RTM_NEWADDR, &[10, 64], 22);
+        // A route to 2001:db8::/32 isn't interesting
+        buf.extend(message(RTM_NEWROUTE, &[10, 32], 12));
+        // The default route is
+        buf.extend(message(RTM_DELROUTE, &[2, 0], 12));
+        // Some other message type
+        buf.extend(message(16, &[0, 0], 16));
+        assert_eq!(
+            parse_events(&buf),
+            vec![Event::Address, Event::DefaultRoute]
+        );
+
+        // A truncated message is ignored
+        let mut truncated = message(RTM_NEWADDR, &[2, 24], 8);
+        truncated.truncate(20);
+        assert_eq!(parse_events(&truncated), vec![]);
+    }
+}